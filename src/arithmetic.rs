@@ -0,0 +1,491 @@
+use crate::variables::Variables;
+use std::fmt;
+
+/// An arithmetic expression that can't be evaluated, like `1 / 0`.
+#[derive(Debug)]
+pub struct ArithmeticError {
+    pub expression: String,
+    pub message: String,
+}
+
+impl fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.expression, self.message)
+    }
+}
+
+// longest first, so `<<=` isn't read as `<<` and `=`
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "++", "--", "+=", "-=",
+    "*=", "/=", "%=", "&=", "|=", "^=", "+", "-", "*", "/", "%", "<", ">", "=", "&", "|", "^", "~",
+    "!", "?", ":", ",", "(", ")",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    // `=` or a compound assignment like `+=`
+    Assign(&'static str, String, Box<Expr>),
+    // ++x and --x
+    PreIncrement(String, i64),
+    // x++ and x--
+    PostIncrement(String, i64),
+}
+
+/// Evaluates a shell arithmetic expression, as in `$((...))` and `((...))`. Variables are
+/// referenced by name without a `$` and count as 0 when unset or empty.
+pub fn evaluate(expression: &str, vars: &mut Variables) -> Result<i64, ArithmeticError> {
+    Evaluator { vars, depth: 0 }.evaluate(expression)
+}
+
+struct Evaluator<'a> {
+    vars: &'a mut Variables,
+    // how deep we are in variables whose values are expressions themselves
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn evaluate(&mut self, expression: &str) -> Result<i64, ArithmeticError> {
+        let error = |message: &str| ArithmeticError {
+            expression: expression.trim().to_string(),
+            message: message.to_string(),
+        };
+        let tokens = tokenize(expression).map_err(|message| error(&message))?;
+        if tokens.is_empty() {
+            return Ok(0);
+        }
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let expr = parser.parse_comma().map_err(|message| error(&message))?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            let message = format!("syntax error in expression (error token is \"{}\")", token);
+            return Err(error(&message));
+        }
+        self.eval(&expr).map_err(|message| error(&message))
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<i64, String> {
+        let value = match expr {
+            Expr::Number(n) => *n,
+            Expr::Variable(name) => self.variable(name)?,
+            Expr::Unary(op, operand) => {
+                let operand = self.eval(operand)?;
+                match *op {
+                    "-" => operand.wrapping_neg(),
+                    "+" => operand,
+                    "!" => (operand == 0) as i64,
+                    "~" => !operand,
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Binary("&&", left, right) => {
+                (self.eval(left)? != 0 && self.eval(right)? != 0) as i64
+            }
+            Expr::Binary("||", left, right) => {
+                (self.eval(left)? != 0 || self.eval(right)? != 0) as i64
+            }
+            Expr::Binary(",", left, right) => {
+                self.eval(left)?;
+                self.eval(right)?
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                apply(op, left, right)?
+            }
+            Expr::Conditional(condition, then, otherwise) => match self.eval(condition)? {
+                0 => self.eval(otherwise)?,
+                _ => self.eval(then)?,
+            },
+            Expr::Assign(op, name, value) => {
+                let value = self.eval(value)?;
+                let value = match op.strip_suffix('=') {
+                    Some("") => value,
+                    Some(op) => apply(op, self.variable(name)?, value)?,
+                    None => unreachable!(),
+                };
+                self.assign(name, value)?
+            }
+            Expr::PreIncrement(name, delta) => {
+                let value = self.variable(name)?.wrapping_add(*delta);
+                self.assign(name, value)?
+            }
+            Expr::PostIncrement(name, delta) => {
+                let value = self.variable(name)?;
+                self.assign(name, value.wrapping_add(*delta))?;
+                value
+            }
+        };
+        Ok(value)
+    }
+
+    /// A variable's value, which may itself be an expression.
+    fn variable(&mut self, name: &str) -> Result<i64, String> {
+        let value = self.vars.get(name).unwrap_or_default().trim().to_string();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = parse_number(&value) {
+            return Ok(n);
+        }
+        if self.depth >= 64 {
+            return Err("expression recursion level exceeded".to_string());
+        }
+        self.depth += 1;
+        let result = self.evaluate(&value).map_err(|e| e.message);
+        self.depth -= 1;
+        result
+    }
+
+    fn assign(&mut self, name: &str, value: i64) -> Result<i64, String> {
+        self.vars
+            .set(name, &value.to_string())
+            .map_err(|e| e.to_string())?;
+        Ok(value)
+    }
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    let value = match op {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("division by 0".to_string()),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err("exponent less than 0".to_string()),
+        "**" => left.wrapping_pow(right.try_into().unwrap_or(u32::MAX)),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "&" => left & right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "<" => (left < right) as i64,
+        ">" => (left > right) as i64,
+        "<=" => (left <= right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        _ => unreachable!(),
+    };
+    Ok(value)
+}
+
+/// Parses an integer constant: decimal, octal with a leading 0, hex with a leading 0x, or
+/// `base#digits` for bases 2 to 64.
+fn parse_number(text: &str) -> Result<i64, String> {
+    let invalid = || format!("value too great for base (error token is \"{}\")", text);
+    let (base, digits) = match text.split_once('#') {
+        Some((base, digits)) => match base.parse::<u32>() {
+            Ok(base @ 2..=64) => (base, digits),
+            _ => {
+                let message = format!("invalid arithmetic base (error token is \"{}\")", text);
+                return Err(message);
+            }
+        },
+        None => match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(digits) => (16, digits),
+            None if text.len() > 1 && text.starts_with('0') => (8, &text[1..]),
+            None => (10, text),
+        },
+    };
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+    digits.chars().try_fold(0i64, |value, c| {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            // letters are 10 to 35, and for bases past 36 upper case continues from 36
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base > 36 => c as u32 - 'A' as u32 + 36,
+            'A'..='Z' => c as u32 - 'A' as u32 + 10,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        Ok(value.wrapping_mul(base as i64).wrapping_add(digit as i64))
+    })
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            // numbers can have a base, as in 16#ff or 64#@_
+            let in_word = |ch: char| {
+                ch.is_ascii_alphanumeric() || ch == '_' || (c.is_ascii_digit() && "#@".contains(ch))
+            };
+            while i < chars.len() && in_word(chars[i]) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            match c.is_ascii_digit() {
+                true => tokens.push(Token::Number(parse_number(&word)?)),
+                false => tokens.push(Token::Name(word)),
+            }
+        } else {
+            let rest: String = chars[i..].iter().take(3).collect();
+            let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) else {
+                let rest: String = chars[i..].iter().collect();
+                return Err(format!(
+                    "syntax error: invalid arithmetic operator (error token is \"{}\")",
+                    rest
+                ));
+            };
+            tokens.push(Token::Operator(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Operator(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+// binary operators from loosest to tightest, each level associating to the left
+const BINARY_LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Parser {
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.peek_operator() {
+            Some(found) if found == op => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(format!("syntax error: `{}' expected", op)),
+        }
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_assignment()?;
+        while self.peek_operator() == Some(",") {
+            self.position += 1;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(self.parse_assignment()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_assignment(&mut self) -> Result<Expr, String> {
+        if let (Some(Token::Name(name)), Some(Token::Operator(op))) = (
+            self.tokens.get(self.position),
+            self.tokens.get(self.position + 1),
+        ) {
+            if op.ends_with('=') && !matches!(*op, "==" | "!=" | "<=" | ">=") {
+                let (name, op) = (name.clone(), *op);
+                self.position += 2;
+                let value = self.parse_assignment()?;
+                return Ok(Expr::Assign(op, name, Box::new(value)));
+            }
+        }
+        self.parse_conditional()
+    }
+
+    fn parse_conditional(&mut self) -> Result<Expr, String> {
+        let condition = self.parse_binary(0)?;
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+        self.position += 1;
+        let then = self.parse_comma()?;
+        self.expect(":")?;
+        let otherwise = self.parse_conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(ops) = BINARY_LEVELS.get(level) else {
+            return self.parse_power();
+        };
+        let mut expr = self.parse_binary(level + 1)?;
+        while let Some(op) = self.peek_operator().filter(|op| ops.contains(op)) {
+            self.position += 1;
+            let right = self.parse_binary(level + 1)?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(right));
+        }
+        Ok(expr)
+    }
+
+    /// `**` associates to the right.
+    fn parse_power(&mut self) -> Result<Expr, String> {
+        let base = self.parse_unary()?;
+        if self.peek_operator() != Some("**") {
+            return Ok(base);
+        }
+        self.position += 1;
+        let exponent = self.parse_power()?;
+        Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)))
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.peek_operator() {
+            Some(op @ ("++" | "--")) => {
+                self.position += 1;
+                let delta = match op {
+                    "++" => 1,
+                    _ => -1,
+                };
+                match self.tokens.get(self.position) {
+                    Some(Token::Name(name)) => {
+                        let name = name.clone();
+                        self.position += 1;
+                        Ok(Expr::PreIncrement(name, delta))
+                    }
+                    _ => Err("syntax error: operand expected".to_string()),
+                }
+            }
+            Some(op @ ("-" | "+" | "!" | "~")) => {
+                self.position += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let expr = self.parse_primary()?;
+        if let (Expr::Variable(name), Some(op @ ("++" | "--"))) = (&expr, self.peek_operator()) {
+            let delta = match op {
+                "++" => 1,
+                _ => -1,
+            };
+            let name = name.clone();
+            self.position += 1;
+            return Ok(Expr::PostIncrement(name, delta));
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Name(name)) => Ok(Expr::Variable(name)),
+            Some(Token::Operator("(")) => {
+                let expr = self.parse_comma()?;
+                self.expect(")").map_err(|_| "missing `)'".to_string())?;
+                Ok(expr)
+            }
+            Some(token) => Err(format!(
+                "syntax error: operand expected (error token is \"{}\")",
+                token
+            )),
+            None => Err("syntax error: operand expected".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Result<i64, ArithmeticError> {
+        evaluate(expression, &mut Variables::default())
+    }
+
+    #[test]
+    fn follows_c_precedence() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(eval("2 ** 3 ** 2").unwrap(), 512);
+        assert_eq!(eval("-2 ** 2").unwrap(), 4);
+        assert_eq!(eval("1 + 2 << 1").unwrap(), 6);
+        assert_eq!(eval("1 | 2 & 0").unwrap(), 1);
+        assert_eq!(eval("0 || 1 && 0").unwrap(), 0);
+        assert_eq!(eval("1 < 2 == 1").unwrap(), 1);
+        assert_eq!(eval("0 ? 1 : 1 ? 2 : 3").unwrap(), 2);
+        assert_eq!(eval("7 - 2 - 1").unwrap(), 4);
+    }
+
+    #[test]
+    fn assigns_to_variables() {
+        let mut vars = Variables::default();
+        assert_eq!(evaluate("x = 2, x += 3, x * 2", &mut vars).unwrap(), 10);
+        assert_eq!(vars.get("x"), Some("5"));
+        assert_eq!(evaluate("x++ + ++x", &mut vars).unwrap(), 12);
+    }
+
+    #[test]
+    fn rejects_division_by_zero() {
+        let error = eval("1 / 0").unwrap_err();
+        assert_eq!(error.message, "division by 0");
+        assert!(eval("5 % (2 - 2)").is_err());
+        // the unused branch isn't evaluated
+        assert_eq!(eval("0 && 1 / 0").unwrap(), 0);
+    }
+
+    #[test]
+    fn wraps_around_on_overflow() {
+        assert_eq!(eval("9223372036854775807 + 1").unwrap(), i64::MIN);
+        assert_eq!(eval("2 ** 64").unwrap(), 0);
+        assert_eq!(eval("-9223372036854775807 - 2").unwrap(), i64::MAX);
+    }
+
+    #[test]
+    fn rejects_negative_exponents() {
+        assert_eq!(eval("2 ** -1").unwrap_err().message, "exponent less than 0");
+    }
+
+    #[test]
+    fn reads_numbers_in_other_bases() {
+        assert_eq!(eval("0x1f + 017 + 2#101").unwrap(), 31 + 15 + 5);
+        assert_eq!(eval("64#_").unwrap(), 63);
+        assert!(eval("08").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::word::{Word, WordPart};
+
+/// Expands the unquoted brace expressions in a word, like `a{b,c}d` or `{1..10..2}`, into the
+/// words they stand for, left to right. Braces that don't form a list or a sequence stay as
+/// they are, and quoted or escaped braces are never touched.
+pub fn expand(word: &Word) -> Vec<Word> {
+    // unquoted text goes character by character so braces and commas can be found in it
+    let mut items = Vec::new();
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(text) => {
+                items.extend(text.chars().map(|c| WordPart::Literal(c.to_string())))
+            }
+            part => items.push(part.clone()),
+        }
+    }
+    expand_items(&items).into_iter().map(join).collect()
+}
+
+fn expand_items(items: &[WordPart]) -> Vec<Vec<WordPart>> {
+    for open in 0..items.len() {
+        if char_at(items, open) != Some('{') {
+            continue;
+        }
+        let Some((close, commas)) = find_close(items, open) else {
+            continue;
+        };
+        let alternatives = match commas.is_empty() {
+            true => match sequence(&items[open + 1..close]) {
+                Some(sequence) => sequence,
+                None => continue,
+            },
+            false => {
+                let mut alternatives = Vec::new();
+                let mut start = open + 1;
+                for end in commas.into_iter().chain(std::iter::once(close)) {
+                    alternatives.push(items[start..end].to_vec());
+                    start = end + 1;
+                }
+                alternatives
+            }
+        };
+
+        // each alternative gets the rest of the word, which may hold more braces
+        let mut words = Vec::new();
+        for alternative in alternatives {
+            let mut word = items[..open].to_vec();
+            word.extend(alternative);
+            word.extend_from_slice(&items[close + 1..]);
+            words.extend(expand_items(&word));
+        }
+        return words;
+    }
+    vec![items.to_vec()]
+}
+
+/// The unquoted character an item is, if it is one.
+fn char_at(items: &[WordPart], i: usize) -> Option<char> {
+    match items.get(i)? {
+        WordPart::Literal(text) => text.chars().next(),
+        _ => None,
+    }
+}
+
+/// Finds the `}` matching the `{` at `open`, along with the commas between them that aren't in
+/// nested braces.
+fn find_close(items: &[WordPart], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for i in open..items.len() {
+        match char_at(items, i) {
+            Some('{') => depth += 1,
+            Some('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((i, commas));
+                }
+            }
+            Some(',') if depth == 1 => commas.push(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The words of a sequence like `1..10`, `01..20..2` or `a..e`, if that's what's between the
+/// braces.
+fn sequence(items: &[WordPart]) -> Option<Vec<Vec<WordPart>>> {
+    let text = (0..items.len())
+        .map(|i| char_at(items, i))
+        .collect::<Option<String>>()?;
+    let mut bounds = text.split("..");
+    let (start, end) = (bounds.next()?, bounds.next()?);
+    let step = match bounds.next() {
+        Some(step) => step.parse::<i64>().ok()?.unsigned_abs().max(1),
+        None => 1,
+    };
+    if bounds.next().is_some() {
+        return None;
+    }
+
+    let words: Vec<String> = match (start.parse::<i64>(), end.parse::<i64>()) {
+        (Ok(first), Ok(last)) => {
+            // a leading zero on either end pads every number to the same width
+            let padded =
+                |bound: &str| bound.trim_start_matches('-').starts_with('0') && bound.len() > 1;
+            let width = match padded(start) || padded(end) {
+                true => start.len().max(end.len()),
+                false => 0,
+            };
+            range(first, last, step)
+                .map(|n| format!("{:0width$}", n, width = width))
+                .collect()
+        }
+        _ => {
+            let (first, last) = (single_char(start)?, single_char(end)?);
+            range(first as i64, last as i64, step)
+                .filter_map(|c| char::from_u32(c as u32))
+                .map(String::from)
+                .collect()
+        }
+    };
+    Some(
+        words
+            .into_iter()
+            .map(|word| vec![WordPart::Literal(word)])
+            .collect(),
+    )
+}
+
+fn single_char(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+        _ => None,
+    }
+}
+
+/// Counts from `first` to `last` inclusive, in whichever direction that is.
+fn range(first: i64, last: i64, step: u64) -> impl Iterator<Item = i64> {
+    let count = first.abs_diff(last) / step;
+    // stops rather than overflowing, though every step stays between the two ends
+    (0..=count).map_while(move |i| {
+        let offset = i.checked_mul(step)?;
+        match first <= last {
+            true => first.checked_add_unsigned(offset),
+            false => first.checked_sub_unsigned(offset),
+        }
+    })
+}
+
+/// Puts the items of a word back together, merging neighbouring text.
+fn join(items: Vec<WordPart>) -> Word {
+    let mut parts = Vec::new();
+    for item in items {
+        match (parts.last_mut(), item) {
+            (Some(WordPart::Literal(text)), WordPart::Literal(more)) => text.push_str(&more),
+            (_, item) => parts.push(item),
+        }
+    }
+    Word { parts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<String> {
+        expand(&Word::literal(text))
+            .iter()
+            .map(Word::unquoted_text)
+            .collect()
+    }
+
+    #[test]
+    fn expands_lists_and_sequences_left_to_right() {
+        assert_eq!(words("{a,b}{1..3}"), ["a1", "a2", "a3", "b1", "b2", "b3"]);
+        assert_eq!(words("x{a,{b,c}}y"), ["xay", "xby", "xcy"]);
+        assert_eq!(words("{5..1..2}"), ["5", "3", "1"]);
+        assert_eq!(words("{a..e..2}"), ["a", "c", "e"]);
+    }
+
+    #[test]
+    fn pads_numbers_with_leading_zeros() {
+        assert_eq!(words("{08..11}"), ["08", "09", "10", "11"]);
+        assert_eq!(words("{-1..01}"), ["-1", "00", "01"]);
+    }
+
+    #[test]
+    fn leaves_other_braces_alone() {
+        assert_eq!(words("{a}"), ["{a}"]);
+        assert_eq!(words("{}"), ["{}"]);
+        assert_eq!(words("{1..a}"), ["{1..a}"]);
+        let quoted = Word {
+            parts: vec![WordPart::SingleQuoted("{a,b}".to_string())],
+        };
+        assert_eq!(expand(&quoted), [quoted]);
+    }
+
+    #[test]
+    fn handles_empty_alternatives_and_zero_steps() {
+        assert_eq!(words("x{,y}z"), ["xz", "xyz"]);
+        assert_eq!(words("{1..3..0}"), ["1", "2", "3"]);
+        assert_eq!(words("{c..a}"), ["c", "b", "a"]);
+        assert_eq!(words("{a,b}.{x}"), ["a.{x}", "b.{x}"]);
+    }
+
+    #[test]
+    fn sequences_at_the_ends_of_the_integer_range_dont_overflow() {
+        assert_eq!(
+            words("{9223372036854775806..9223372036854775807}"),
+            ["9223372036854775806", "9223372036854775807"]
+        );
+        assert_eq!(
+            words("{-9223372036854775808..9223372036854775807..9223372036854775807}"),
+            ["-9223372036854775808", "-1", "9223372036854775806"]
+        );
+        assert_eq!(
+            words("{9223372036854775807..-9223372036854775808..-9223372036854775808}"),
+            ["9223372036854775807", "-1"]
+        );
+        // a bound that isn't an i64 leaves the braces alone
+        assert_eq!(
+            words("{1..9223372036854775808}"),
+            ["{1..9223372036854775808}"]
+        );
+    }
+}
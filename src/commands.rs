@@ -1,22 +1,31 @@
 use crate::completer::MyHelper;
-use crate::enums::Command;
+use crate::enums::{Command, LoopControl};
+use crate::jobs::{JobState, JobTable, ProcessGroup};
+use crate::options::ShellOptions;
 use crate::util;
+use crate::util::Streams;
+use crate::variables::{is_valid_name, Variable, Variables};
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd;
+use nix::unistd::Pid;
 use rustyline::history::{DefaultHistory, History, SearchDirection, SearchResult};
 use rustyline::Editor;
 use std::io::Write;
+use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::os::unix::process::CommandExt;
-use std::{path::PathBuf, process};
+use std::{io, path::PathBuf, process};
 
 pub fn history_cmd(
     args: &[String],
     iostream: &mut dyn Write,
     editor: &mut Editor<MyHelper, DefaultHistory>,
     last_saved_history_idx: &mut usize,
-) {
+) -> i32 {
     let history = editor.history();
     let len = history.len();
 
-    match args.get(0).map(String::as_str) {
+    match args.first().map(String::as_str) {
         Some("-r") => {
             if let Some(path) = args.get(1) {
                 let new_hist = util::read_history(path);
@@ -54,67 +63,220 @@ pub fn history_cmd(
                 if let Ok(Some(SearchResult { entry, .. })) =
                     history.get(i, SearchDirection::Forward)
                 {
-                    writeln!(iostream, "    {}  {}", i + 1, entry).unwrap();
+                    let _ = writeln!(iostream, "    {}  {}", i + 1, entry);
                 }
             }
         }
         None => {
             for (i, entry) in history.iter().enumerate() {
-                writeln!(iostream, "    {}  {}", i + 1, entry).unwrap();
+                let _ = writeln!(iostream, "    {}  {}", i + 1, entry);
             }
         }
     }
+    0
 }
 
-pub fn cd_cmd(args: &[String], err_stream: &mut dyn Write) {
-    let home = std::env::var("HOME").unwrap_or_else(|_| "/".into());
-    let dir = args.get(0).unwrap_or(&home);
-    let path = if dir.starts_with('~') {
-        PathBuf::from(dir.replacen('~', &home, 1))
-    } else {
-        PathBuf::from(dir)
+pub fn cd_cmd(
+    args: &[String],
+    iostream: &mut dyn Write,
+    err_stream: &mut dyn Write,
+    vars: &mut Variables,
+) -> i32 {
+    // `cd -` goes back to the previous directory and says where that is
+    let (dir, announce) = match args.first().map(String::as_str) {
+        None => match vars.get("HOME") {
+            Some(home) => (home.to_string(), false),
+            None => {
+                let _ = writeln!(err_stream, "cd: HOME not set");
+                return 1;
+            }
+        },
+        Some("-") => match vars.get("OLDPWD") {
+            Some(oldpwd) => (oldpwd.to_string(), true),
+            None => {
+                let _ = writeln!(err_stream, "cd: OLDPWD not set");
+                return 1;
+            }
+        },
+        Some(dir) => (dir.to_string(), false),
     };
 
-    if std::env::set_current_dir(&path).is_err() {
-        writeln!(err_stream, "cd: {}: No such file or directory", dir).unwrap();
+    let previous = std::env::current_dir().ok();
+    if std::env::set_current_dir(&dir).is_err() {
+        let _ = writeln!(err_stream, "cd: {}: No such file or directory", dir);
+        return 1;
     }
+    // readonly PWD or OLDPWD don't stop the directory changing
+    if let Some(previous) = previous {
+        let _ = vars.set("OLDPWD", &previous.to_string_lossy());
+    }
+    if let Ok(current) = std::env::current_dir() {
+        let _ = vars.set("PWD", &current.to_string_lossy());
+        if announce {
+            let _ = writeln!(iostream, "{}", current.display());
+        }
+    }
+    0
 }
 
-pub fn pwd_cmd(iostream: &mut dyn Write) {
+pub fn pwd_cmd(iostream: &mut dyn Write) -> i32 {
     let current_dir = std::env::current_dir().unwrap();
-    writeln!(iostream, "{}", current_dir.display()).unwrap();
+    let _ = writeln!(iostream, "{}", current_dir.display());
+    0
 }
 
-pub fn external_cmd(
+/// Builds a child process that gets our descriptors directly, so its output is live and never
+/// buffered by the shell.
+fn build_command(
     path: PathBuf,
     args: &[String],
-    iostream: &mut dyn Write,
-    err_stream: &mut dyn Write,
-) {
+    env: &[(String, String)],
+    mut streams: Streams,
+    group: Option<ProcessGroup>,
+) -> io::Result<process::Command> {
     let file_name = path.file_name().unwrap_or_default().to_os_string();
 
-    match process::Command::new(path)
+    let mut closed = Vec::new();
+    let mut stdio = |n: u8| match streams.take(n) {
+        Some(fd) => process::Stdio::from(fd),
+        None => {
+            closed.push(n as RawFd);
+            process::Stdio::null()
+        }
+    };
+    let mut command = process::Command::new(path);
+    command
         .arg0(file_name)
         .args(args)
-        .output()
-    {
-        Ok(output) => {
-            iostream.write_all(&output.stdout).unwrap();
-            err_stream.write_all(&output.stderr).unwrap();
+        .envs(env.iter().cloned())
+        .stdin(stdio(0))
+        .stdout(stdio(1))
+        .stderr(stdio(2));
+
+    // move the sources above any fd we might target before forking, so the child only has to
+    // place them
+    let mut moved = Vec::new();
+    for (n, fd) in streams.take_extra() {
+        let high = fcntl(&fd, FcntlArg::F_DUPFD_CLOEXEC(256))?;
+        // SAFETY: fcntl just returned this descriptor, so nothing else owns it
+        moved.push((n as RawFd, unsafe { OwnedFd::from_raw_fd(high) }));
+    }
+    // SAFETY: only async-signal-safe calls happen before exec, and nothing is allocated
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(group) = group {
+                group.join()?;
+            }
+            for (n, high) in &moved {
+                // the target must stay open across exec, so it isn't owned here
+                let _ = unistd::dup2_raw(high, *n)?.into_raw_fd();
+            }
+            for fd in &closed {
+                let _ = unistd::close(*fd);
+            }
+            Ok(())
+        });
+    }
+    Ok(command)
+}
+
+/// Starts an external command, returning its pid for the caller to wait on, or the exit status
+/// if it couldn't be started.
+pub fn external_cmd(
+    path: PathBuf,
+    args: &[String],
+    env: &[(String, String)],
+    streams: Streams,
+    group: Option<ProcessGroup>,
+) -> Result<Pid, i32> {
+    let mut err_stream = streams.dup_file(2);
+    let name = path.display().to_string();
+
+    match build_command(path, args, env, streams, group).and_then(|mut command| command.spawn()) {
+        Ok(child) => {
+            let pid = Pid::from_raw(child.id() as i32);
+            if let Some(group) = group {
+                group.admit(pid);
+            }
+            Ok(pid)
+        }
+        Err(e) => {
+            let _ = writeln!(err_stream, "{}: {}", name, util::io_error_message(&e));
+            Err(not_executable_status(&e))
         }
-        Err(e) => writeln!(err_stream, "Error: {}", e).unwrap(),
     }
 }
 
-pub fn invalid_cmd(name: &str, err_stream: &mut dyn Write) {
-    writeln!(err_stream, "{}: command not found", name).unwrap();
+/// Replaces the process with an external command, returning the exit status only if it couldn't
+/// be started.
+pub fn exec_external(
+    path: PathBuf,
+    args: &[String],
+    env: &[(String, String)],
+    streams: Streams,
+) -> i32 {
+    let mut err_stream = streams.dup_file(2);
+    let name = path.display().to_string();
+
+    let e = match build_command(path, args, env, streams, None) {
+        Ok(mut command) => command.exec(),
+        Err(e) => e,
+    };
+    let _ = writeln!(err_stream, "{}: {}", name, util::io_error_message(&e));
+    not_executable_status(&e)
+}
+
+/// 127 if the program doesn't exist, 126 if it exists but can't be run.
+fn not_executable_status(e: &io::Error) -> i32 {
+    match e.kind() {
+        io::ErrorKind::NotFound => 127,
+        _ => 126,
+    }
+}
+
+pub fn exec_cmd(
+    args: &[String],
+    env: &[(String, String)],
+    streams: Streams,
+    fds: &mut Streams,
+) -> i32 {
+    let Some(name) = args.first() else {
+        // without a command the redirections apply to the shell itself
+        *fds = streams;
+        return 0;
+    };
+
+    let mut err_stream = streams.dup_file(2);
+    match name.parse::<Command>() {
+        Ok(Command::External(path)) => {
+            let e = match build_command(path, &args[1..], env, streams, None) {
+                Ok(mut command) => command.exec(),
+                Err(e) => e,
+            };
+            let _ = writeln!(err_stream, "exec: {}: {}", name, util::io_error_message(&e));
+            not_executable_status(&e)
+        }
+        _ => {
+            let _ = writeln!(err_stream, "exec: {}: not found", name);
+            127
+        }
+    }
+}
+
+pub fn invalid_cmd(name: &str, err_stream: &mut dyn Write) -> i32 {
+    if name.contains('/') {
+        let _ = writeln!(err_stream, "{}: No such file or directory", name);
+    } else {
+        let _ = writeln!(err_stream, "{}: command not found", name);
+    }
+    127
 }
 
-pub fn type_cmd(args: &[String], iostream: &mut dyn Write, err_stream: &mut dyn Write) {
-    if let Some(name) = args.get(0) {
+pub fn type_cmd(args: &[String], iostream: &mut dyn Write, err_stream: &mut dyn Write) -> i32 {
+    if let Some(name) = args.first() {
         match name.parse::<Command>() {
             Ok(Command::External(path)) => {
-                writeln!(iostream, "{} is {}", name, path.to_str().unwrap()).unwrap();
+                let _ = writeln!(iostream, "{} is {}", name, path.to_str().unwrap());
             }
             Ok(
                 Command::Exit
@@ -122,29 +284,62 @@ pub fn type_cmd(args: &[String], iostream: &mut dyn Write, err_stream: &mut dyn
                 | Command::Type
                 | Command::Pwd
                 | Command::Cd
-                | Command::History,
+                | Command::History
+                | Command::Exec
+                | Command::Jobs
+                | Command::Fg
+                | Command::Bg
+                | Command::Disown
+                | Command::Kill
+                | Command::Export
+                | Command::Unset
+                | Command::Readonly
+                | Command::Shopt
+                | Command::Break
+                | Command::Continue,
             ) => {
-                writeln!(iostream, "{} is a shell builtin", name).unwrap();
+                let _ = writeln!(iostream, "{} is a shell builtin", name);
             }
             Ok(Command::Invalid) | Err(_) => {
-                writeln!(err_stream, "{}: not found", name).unwrap();
+                let _ = writeln!(err_stream, "{}: not found", name);
+                return 1;
             }
         }
+        0
     } else {
-        writeln!(err_stream, "type: missing operand").unwrap();
+        let _ = writeln!(err_stream, "type: missing operand");
+        1
     }
 }
 
-pub fn echo_cmd(input: &[String], iostream: &mut dyn Write) {
-    writeln!(iostream, "{}", input.join(" ")).unwrap();
+pub fn echo_cmd(input: &[String], iostream: &mut dyn Write) -> i32 {
+    let _ = writeln!(iostream, "{}", input.join(" "));
+    0
 }
 
 pub fn exit_cmd(
     args: &[String],
+    err_stream: &mut dyn Write,
     editor: &mut Editor<MyHelper, DefaultHistory>,
     last_saved_history_idx: &mut usize,
-) {
-    let code = args.get(0).and_then(|s| s.parse::<i32>().ok()).unwrap_or(0);
+    last_status: i32,
+    subshell: bool,
+) -> i32 {
+    // without an argument, exit with the status of the last command
+    let code = match args.first() {
+        Some(arg) => match arg.parse::<i32>() {
+            Ok(code) => code & 0xff,
+            Err(_) => {
+                let _ = writeln!(err_stream, "exit: {}: numeric argument required", arg);
+                2
+            }
+        },
+        None => last_status,
+    };
+    // a subshell has a copy of the history, which the shell it was forked from saves
+    if subshell {
+        process::exit(code);
+    }
     let first_i = *last_saved_history_idx;
     let history = editor.history();
     let len = history.len();
@@ -159,3 +354,479 @@ pub fn exit_cmd(
 
     process::exit(code);
 }
+
+pub fn jobs_cmd(args: &[String], iostream: &mut dyn Write, jobs: &mut JobTable) -> i32 {
+    // finished jobs are listed one last time, then forgotten
+    for line in jobs.reap() {
+        let _ = writeln!(iostream, "{}", line);
+    }
+
+    let ids: Vec<usize> = jobs.iter().map(|job| job.id).collect();
+    for id in ids {
+        let line = jobs.describe(id);
+        match args.first().map(String::as_str) {
+            Some("-p") => {
+                let pgid = jobs.get_mut(id).map(|job| job.pgid).unwrap();
+                let _ = writeln!(iostream, "{}", pgid);
+            }
+            Some("-l") => {
+                // the pid goes between the job number and its status
+                let pgid = jobs.get_mut(id).map(|job| job.pgid).unwrap();
+                let (number, rest) = line.split_at(line.find(' ').unwrap_or(0));
+                let _ = writeln!(iostream, "{} {}{}", number, pgid, rest);
+            }
+            _ => {
+                let _ = writeln!(iostream, "{}", line);
+            }
+        }
+    }
+    0
+}
+
+/// Finds the job named by a job control builtin's argument, reporting it if there's none.
+fn find_job(
+    name: &str,
+    spec: Option<&String>,
+    err_stream: &mut dyn Write,
+    jobs: &JobTable,
+) -> Option<usize> {
+    if !jobs.has_job_control() {
+        let _ = writeln!(err_stream, "{}: no job control", name);
+        return None;
+    }
+    let id = jobs.find(spec.map(String::as_str));
+    if id.is_none() {
+        let spec = spec.map_or("current", String::as_str);
+        let _ = writeln!(err_stream, "{}: {}: no such job", name, spec);
+    }
+    id
+}
+
+pub fn fg_cmd(
+    args: &[String],
+    iostream: &mut dyn Write,
+    err_stream: &mut dyn Write,
+    jobs: &mut JobTable,
+) -> i32 {
+    let Some(id) = find_job("fg", args.first(), err_stream, jobs) else {
+        return 1;
+    };
+    let job = jobs.remove(id).unwrap();
+    let _ = writeln!(iostream, "{}", job.command);
+    jobs.continue_foreground(job)
+}
+
+pub fn bg_cmd(
+    args: &[String],
+    iostream: &mut dyn Write,
+    err_stream: &mut dyn Write,
+    jobs: &mut JobTable,
+) -> i32 {
+    let Some(id) = find_job("bg", args.first(), err_stream, jobs) else {
+        return 1;
+    };
+    let _ = jobs.signal(id, Signal::SIGCONT);
+    let job = jobs.get_mut(id).unwrap();
+    job.state = JobState::Running;
+    let _ = writeln!(iostream, "[{}] {} &", job.id, job.command);
+    0
+}
+
+pub fn disown_cmd(args: &[String], err_stream: &mut dyn Write, jobs: &mut JobTable) -> i32 {
+    if args.first().map(String::as_str) == Some("-a") {
+        let ids: Vec<usize> = jobs.iter().map(|job| job.id).collect();
+        for id in ids {
+            jobs.remove(id);
+        }
+        return 0;
+    }
+
+    let Some(id) = jobs.find(args.first().map(String::as_str)) else {
+        let spec = args.first().map_or("current", String::as_str);
+        let _ = writeln!(err_stream, "disown: {}: no such job", spec);
+        return 1;
+    };
+    jobs.remove(id);
+    0
+}
+
+/// Parses a signal given as a number, a name like `TERM`, or a full name like `SIGTERM`.
+fn parse_signal(name: &str) -> Option<Signal> {
+    match name.parse::<i32>() {
+        Ok(number) => Signal::try_from(number).ok(),
+        Err(_) => {
+            let name = name.to_ascii_uppercase();
+            match name.starts_with("SIG") {
+                true => name.parse().ok(),
+                false => format!("SIG{}", name).parse().ok(),
+            }
+        }
+    }
+}
+
+pub fn kill_cmd(
+    args: &[String],
+    iostream: &mut dyn Write,
+    err_stream: &mut dyn Write,
+    jobs: &mut JobTable,
+) -> i32 {
+    let mut signal = Signal::SIGTERM;
+    let mut targets = args;
+
+    match args.first().map(String::as_str) {
+        Some("-l") => {
+            let names: Vec<&str> = Signal::iterator()
+                .map(|sig| sig.as_str().trim_start_matches("SIG"))
+                .collect();
+            let _ = writeln!(iostream, "{}", names.join(" "));
+            return 0;
+        }
+        Some("-s" | "-n") => {
+            let name = args.get(1).map_or("", String::as_str);
+            match parse_signal(name) {
+                Some(sig) => signal = sig,
+                None => {
+                    let _ = writeln!(err_stream, "kill: {}: invalid signal specification", name);
+                    return 1;
+                }
+            }
+            targets = &args[2.min(args.len())..];
+        }
+        Some(arg) if arg.starts_with('-') => {
+            match parse_signal(&arg[1..]) {
+                Some(sig) => signal = sig,
+                None => {
+                    let _ = writeln!(
+                        err_stream,
+                        "kill: {}: invalid signal specification",
+                        &arg[1..]
+                    );
+                    return 1;
+                }
+            }
+            targets = &args[1..];
+        }
+        _ => {}
+    }
+
+    if targets.is_empty() {
+        let _ = writeln!(
+            err_stream,
+            "kill: usage: kill [-s sigspec | -sigspec] pid | jobspec ..."
+        );
+        return 2;
+    }
+
+    let mut status = 0;
+    for target in targets {
+        let result = if target.starts_with('%') {
+            match jobs.find(Some(target)) {
+                Some(id) => {
+                    let result = jobs.signal(id, signal);
+                    if jobs
+                        .get_mut(id)
+                        .is_some_and(|job| job.state == JobState::Stopped)
+                    {
+                        // a stopped job can't act on most signals until it's continued
+                        let _ = jobs.signal(id, Signal::SIGCONT);
+                    }
+                    result
+                }
+                None => {
+                    let _ = writeln!(err_stream, "kill: {}: no such job", target);
+                    status = 1;
+                    continue;
+                }
+            }
+        } else {
+            match target.parse::<i32>() {
+                Ok(pid) => kill(Pid::from_raw(pid), signal),
+                Err(_) => {
+                    let _ = writeln!(
+                        err_stream,
+                        "kill: {}: arguments must be process or job IDs",
+                        target
+                    );
+                    status = 1;
+                    continue;
+                }
+            }
+        };
+        if let Err(e) = result {
+            let _ = writeln!(err_stream, "kill: ({}) - {}", target, e.desc());
+            status = 1;
+        }
+    }
+    status
+}
+
+/// Prints variables the way bash's `declare -p` does, e.g. `declare -x HOME="/root"`.
+fn print_variables<'a>(
+    iostream: &mut dyn Write,
+    vars: impl Iterator<Item = (&'a String, &'a Variable)>,
+) {
+    for (name, var) in vars {
+        let mut flags = String::new();
+        if var.readonly {
+            flags.push('r');
+        }
+        if var.exported {
+            flags.push('x');
+        }
+        if flags.is_empty() {
+            flags.push('-');
+        }
+        match &var.value {
+            Some(value) => {
+                let mut quoted = String::new();
+                for c in value.chars() {
+                    if matches!(c, '"' | '\\' | '$' | '`') {
+                        quoted.push('\\');
+                    }
+                    quoted.push(c);
+                }
+                let _ = writeln!(iostream, "declare -{} {}=\"{}\"", flags, name, quoted);
+            }
+            None => {
+                let _ = writeln!(iostream, "declare -{} {}", flags, name);
+            }
+        }
+    }
+}
+
+/// Sets the variables of `NAME=value` arguments and hands each name to `apply`, for `export`
+/// and `readonly`.
+fn declare_variables(
+    builtin: &str,
+    args: &[&String],
+    err_stream: &mut dyn Write,
+    vars: &mut Variables,
+    apply: impl Fn(&mut Variables, &str),
+) -> i32 {
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_valid_name(name) {
+            let _ = writeln!(err_stream, "{}: `{}': not a valid identifier", builtin, arg);
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            if let Err(e) = vars.set(name, value) {
+                let _ = writeln!(err_stream, "{}: {}", builtin, e);
+                status = 1;
+                continue;
+            }
+        }
+        apply(vars, name);
+    }
+    status
+}
+
+pub fn export_cmd(
+    args: &[String],
+    iostream: &mut dyn Write,
+    err_stream: &mut dyn Write,
+    vars: &mut Variables,
+) -> i32 {
+    let mut exported = true;
+    let mut names = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-n" => exported = false,
+            "-p" => {}
+            _ => names.push(arg),
+        }
+    }
+
+    if names.is_empty() {
+        print_variables(iostream, vars.iter().filter(|(_, var)| var.exported));
+        return 0;
+    }
+    declare_variables("export", &names, err_stream, vars, |vars, name| {
+        vars.export(name, exported)
+    })
+}
+
+pub fn readonly_cmd(
+    args: &[String],
+    iostream: &mut dyn Write,
+    err_stream: &mut dyn Write,
+    vars: &mut Variables,
+) -> i32 {
+    let names: Vec<&String> = args.iter().filter(|arg| *arg != "-p").collect();
+    if names.is_empty() {
+        print_variables(iostream, vars.iter().filter(|(_, var)| var.readonly));
+        return 0;
+    }
+    declare_variables(
+        "readonly",
+        &names,
+        err_stream,
+        vars,
+        Variables::make_readonly,
+    )
+}
+
+pub fn unset_cmd(args: &[String], err_stream: &mut dyn Write, vars: &mut Variables) -> i32 {
+    let mut status = 0;
+    // there are no functions to unset, so -v is the only mode
+    for name in args.iter().filter(|arg| *arg != "-v") {
+        if !is_valid_name(name) {
+            let _ = writeln!(err_stream, "unset: `{}': not a valid identifier", name);
+            status = 1;
+            continue;
+        }
+        if let Err(e) = vars.unset(name) {
+            let _ = writeln!(
+                err_stream,
+                "unset: {}: cannot unset: readonly variable",
+                e.name
+            );
+            status = 1;
+        }
+    }
+    status
+}
+
+pub fn shopt_cmd(
+    args: &[String],
+    iostream: &mut dyn Write,
+    err_stream: &mut dyn Write,
+    options: &mut ShellOptions,
+) -> i32 {
+    let mut set = None;
+    let mut print = false;
+    let mut quiet = false;
+    let mut names = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-s" => set = Some(true),
+            "-u" => set = Some(false),
+            "-p" => print = true,
+            "-q" => quiet = true,
+            flag if flag.starts_with('-') => {
+                let _ = writeln!(err_stream, "shopt: {}: invalid option", flag);
+                let _ = writeln!(err_stream, "shopt: usage: shopt [-pqsu] [optname ...]");
+                return 2;
+            }
+            name => names.push(name),
+        }
+    }
+
+    let mut status = 0;
+    let mut checked = Vec::new();
+    for name in &names {
+        match options.get(name) {
+            Some(on) => checked.push((*name, on)),
+            None => {
+                let _ = writeln!(err_stream, "shopt: {}: invalid shell option name", name);
+                status = 1;
+            }
+        }
+    }
+
+    if let Some(on) = set {
+        for (name, _) in &checked {
+            options.set(name, on);
+        }
+        // with no names, -s and -u list the options that are already on or off
+        if !names.is_empty() {
+            return status;
+        }
+    }
+    if names.is_empty() {
+        checked = ShellOptions::NAMES
+            .iter()
+            .map(|name| (*name, options.get(name).unwrap_or_default()))
+            .filter(|(_, on)| set.map_or(true, |set| *on == set))
+            .collect();
+    }
+
+    for (name, on) in &checked {
+        if !on {
+            status = status.max(1);
+        }
+        if quiet {
+            continue;
+        }
+        let state = match (print, on) {
+            (true, true) => format!("shopt -s {}", name),
+            (true, false) => format!("shopt -u {}", name),
+            (false, true) => format!("{:<15}\ton", name),
+            (false, false) => format!("{:<15}\toff", name),
+        };
+        let _ = writeln!(iostream, "{}", state);
+    }
+    // listing every option succeeds whatever their state
+    match names.is_empty() {
+        true => 0,
+        false => status,
+    }
+}
+
+pub fn break_cmd(
+    args: &[String],
+    err_stream: &mut dyn Write,
+    loop_depth: usize,
+    loop_control: &mut Option<LoopControl>,
+) -> i32 {
+    match loop_count("break", args, err_stream, loop_depth) {
+        Ok(count) => {
+            *loop_control = Some(LoopControl::Break(count));
+            0
+        }
+        Err(status) => status,
+    }
+}
+
+pub fn continue_cmd(
+    args: &[String],
+    err_stream: &mut dyn Write,
+    loop_depth: usize,
+    loop_control: &mut Option<LoopControl>,
+) -> i32 {
+    match loop_count("continue", args, err_stream, loop_depth) {
+        Ok(count) => {
+            *loop_control = Some(LoopControl::Continue(count));
+            0
+        }
+        Err(status) => status,
+    }
+}
+
+/// How many enclosing loops `break n` or `continue n` applies to, which is at most as many as
+/// there are.
+fn loop_count(
+    name: &str,
+    args: &[String],
+    err_stream: &mut dyn Write,
+    loop_depth: usize,
+) -> Result<usize, i32> {
+    let count = match args.first() {
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(count) if count > 0 => count as usize,
+            Ok(_) => {
+                let _ = writeln!(err_stream, "{}: {}: loop count out of range", name, arg);
+                return Err(1);
+            }
+            Err(_) => {
+                let _ = writeln!(err_stream, "{}: {}: numeric argument required", name, arg);
+                return Err(128);
+            }
+        },
+        None => 1,
+    };
+    if loop_depth == 0 {
+        let _ = writeln!(
+            err_stream,
+            "{}: only meaningful in a `for', `while', or `until' loop",
+            name
+        );
+        return Err(0);
+    }
+    Ok(count.min(loop_depth))
+}
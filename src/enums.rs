@@ -1,14 +1,78 @@
 use std::fmt::Display;
+use std::os::fd::RawFd;
 use std::path::PathBuf;
 use std::str::FromStr;
 
 use crate::util::check_path;
+use crate::word::Word;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Operator {
     Pipe,
-    Output(Option<u8>), // >, 1>, 2>
-    Append(Option<u8>), // >>, 1>>, 2>>
-    Input,              // <
+    And,                             // &&
+    Or,                              // ||
+    Semi,                            // ;
+    DoubleSemi,                      // ;;, which ends a case item
+    LeftParen,                       // (, which may open a case pattern
+    RightParen,                      // ), which closes a case pattern
+    Newline,    // separates commands like ; but may also continue a pipeline or list
+    Background, // &
+    Output(Option<RawFd>), // >, 1>, 2>
+    Append(Option<RawFd>), // >>, 1>>, 2>>
+    Input(Option<RawFd>), // <, 3<
+    ReadWrite(Option<RawFd>), // <>, 5<>
+    DupOutput(Option<RawFd>), // >&, 1>&, 2>&
+    DupInput(Option<RawFd>), // <&, 3<&
+    OutputAll,  // &>
+    AppendAll,  // &>>
+    HereDoc(Option<RawFd>), // <<
+    HereDocStripTabs(Option<RawFd>), // <<-
+    HereString(Option<RawFd>), // <<<
+}
+
+impl Operator {
+    /// Operators that end a simple command, as opposed to redirections.
+    pub fn is_control(&self) -> bool {
+        matches!(
+            self,
+            Self::Pipe
+                | Self::And
+                | Self::Or
+                | Self::Semi
+                | Self::DoubleSemi
+                | Self::LeftParen
+                | Self::RightParen
+                | Self::Newline
+                | Self::Background
+        )
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fd = |fd: &Option<RawFd>| fd.map(|fd| fd.to_string()).unwrap_or_default();
+        match self {
+            Self::Pipe => write!(f, "|"),
+            Self::And => write!(f, "&&"),
+            Self::Or => write!(f, "||"),
+            Self::Semi => write!(f, ";"),
+            Self::DoubleSemi => write!(f, ";;"),
+            Self::LeftParen => write!(f, "("),
+            Self::RightParen => write!(f, ")"),
+            Self::Newline => writeln!(f),
+            Self::Background => write!(f, "&"),
+            Self::Output(n) => write!(f, "{}>", fd(n)),
+            Self::Append(n) => write!(f, "{}>>", fd(n)),
+            Self::Input(n) => write!(f, "{}<", fd(n)),
+            Self::ReadWrite(n) => write!(f, "{}<>", fd(n)),
+            Self::DupOutput(n) => write!(f, "{}>&", fd(n)),
+            Self::DupInput(n) => write!(f, "{}<&", fd(n)),
+            Self::OutputAll => write!(f, "&>"),
+            Self::AppendAll => write!(f, "&>>"),
+            Self::HereDoc(n) => write!(f, "{}<<", fd(n)),
+            Self::HereDocStripTabs(n) => write!(f, "{}<<-", fd(n)),
+            Self::HereString(n) => write!(f, "{}<<<", fd(n)),
+        }
+    }
 }
 
 impl FromStr for Operator {
@@ -16,14 +80,38 @@ impl FromStr for Operator {
 
     fn from_str(op: &str) -> Result<Self, Self::Err> {
         match op {
-            "|" => Ok(Self::Pipe),
-            ">" => Ok(Self::Output(None)),
-            "1>" => Ok(Self::Output(Some(1))),
-            "2>" => Ok(Self::Output(Some(2))),
-            ">>" => Ok(Self::Append(None)),
-            "1>>" => Ok(Self::Append(Some(1))),
-            "2>>" => Ok(Self::Append(Some(2))),
-            "<" => Ok(Self::Input),
+            "|" => return Ok(Self::Pipe),
+            "&" => return Ok(Self::Background),
+            "&&" => return Ok(Self::And),
+            "||" => return Ok(Self::Or),
+            ";" => return Ok(Self::Semi),
+            ";;" => return Ok(Self::DoubleSemi),
+            "(" => return Ok(Self::LeftParen),
+            ")" => return Ok(Self::RightParen),
+            "\n" => return Ok(Self::Newline),
+            "&>" => return Ok(Self::OutputAll),
+            "&>>" => return Ok(Self::AppendAll),
+            _ => {}
+        }
+
+        // redirections may be prefixed with the fd they apply to, as in 2>> or 3<
+        let split = op.find(|c: char| !c.is_ascii_digit()).ok_or(())?;
+        let (fd, op) = op.split_at(split);
+        let fd = match fd {
+            "" => None,
+            fd => Some(fd.parse::<RawFd>().map_err(|_| ())?),
+        };
+
+        match op {
+            ">" => Ok(Self::Output(fd)),
+            ">>" => Ok(Self::Append(fd)),
+            "<" => Ok(Self::Input(fd)),
+            "<>" => Ok(Self::ReadWrite(fd)),
+            ">&" => Ok(Self::DupOutput(fd)),
+            "<&" => Ok(Self::DupInput(fd)),
+            "<<" => Ok(Self::HereDoc(fd)),
+            "<<-" => Ok(Self::HereDocStripTabs(fd)),
+            "<<<" => Ok(Self::HereString(fd)),
             _ => Err(()),
         }
     }
@@ -40,11 +128,23 @@ pub enum Command {
     Pwd,
     Cd,
     History,
+    Exec,
+    Jobs,
+    Fg,
+    Bg,
+    Disown,
+    Kill,
+    Export,
+    Unset,
+    Readonly,
+    Shopt,
+    Break,
+    Continue,
 }
 
 impl Command {
     pub fn get_builtins() -> Vec<String> {
-        vec![
+        [
             Command::Exit,
             Command::Echo,
             Command::Type,
@@ -52,6 +152,18 @@ impl Command {
             Command::Pwd,
             Command::Cd,
             Command::History,
+            Command::Exec,
+            Command::Jobs,
+            Command::Fg,
+            Command::Bg,
+            Command::Disown,
+            Command::Kill,
+            Command::Export,
+            Command::Unset,
+            Command::Readonly,
+            Command::Shopt,
+            Command::Break,
+            Command::Continue,
         ]
         .iter()
         .map(|cmd| cmd.to_string())
@@ -70,6 +182,18 @@ impl Display for Command {
             Self::Pwd => "pwd",
             Self::Cd => "cd",
             Self::History => "history",
+            Self::Exec => "exec",
+            Self::Jobs => "jobs",
+            Self::Fg => "fg",
+            Self::Bg => "bg",
+            Self::Disown => "disown",
+            Self::Kill => "kill",
+            Self::Export => "export",
+            Self::Unset => "unset",
+            Self::Readonly => "readonly",
+            Self::Shopt => "shopt",
+            Self::Break => "break",
+            Self::Continue => "continue",
         };
         write!(f, "{}", str)
     }
@@ -86,22 +210,127 @@ impl FromStr for Command {
             "pwd" => Self::Pwd,
             "cd" => Self::Cd,
             "history" => Self::History,
+            "exec" => Self::Exec,
+            "jobs" => Self::Jobs,
+            "fg" => Self::Fg,
+            "bg" => Self::Bg,
+            "disown" => Self::Disown,
+            "kill" => Self::Kill,
+            "export" => Self::Export,
+            "unset" => Self::Unset,
+            "readonly" => Self::Readonly,
+            "shopt" => Self::Shopt,
+            "break" => Self::Break,
+            "continue" => Self::Continue,
             _ => check_path(cmd).map(Self::External).unwrap_or(Self::Invalid),
         };
         Ok(result)
     }
 }
 
+/// Where something was in the source it was lexed from, as byte offsets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A token and the source it was lexed from.
+#[derive(Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub enum Token {
-    Word(String),
+    Word(Word),
     Operator(Operator),
+    Arithmetic(Word), // ((expression)), a command of its own
+    Reserved(Reserved),
 }
 
 impl FromStr for Token {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Token::Word(s.to_string()))
+        Ok(Token::Word(Word::literal(s)))
     }
 }
+
+/// Words that are part of the shell's grammar where a command could start, like `if` and `done`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reserved {
+    If,
+    Then,
+    Elif,
+    Else,
+    Fi,
+    While,
+    Until,
+    Do,
+    Done,
+    For,
+    Case,
+    Esac,
+}
+
+impl Reserved {
+    /// Reserved words that are followed by a command rather than a name or a separator.
+    pub fn starts_list(&self) -> bool {
+        matches!(
+            self,
+            Self::If | Self::Then | Self::Elif | Self::Else | Self::While | Self::Until | Self::Do
+        )
+    }
+}
+
+impl Display for Reserved {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Self::If => "if",
+            Self::Then => "then",
+            Self::Elif => "elif",
+            Self::Else => "else",
+            Self::Fi => "fi",
+            Self::While => "while",
+            Self::Until => "until",
+            Self::Do => "do",
+            Self::Done => "done",
+            Self::For => "for",
+            Self::Case => "case",
+            Self::Esac => "esac",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl FromStr for Reserved {
+    type Err = ();
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        match word {
+            "if" => Ok(Self::If),
+            "then" => Ok(Self::Then),
+            "elif" => Ok(Self::Elif),
+            "else" => Ok(Self::Else),
+            "fi" => Ok(Self::Fi),
+            "while" => Ok(Self::While),
+            "until" => Ok(Self::Until),
+            "do" => Ok(Self::Do),
+            "done" => Ok(Self::Done),
+            "for" => Ok(Self::For),
+            "case" => Ok(Self::Case),
+            "esac" => Ok(Self::Esac),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A `break` or `continue` on its way out to the loop it applies to, with how many enclosing
+/// loops are left to go through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopControl {
+    Break(usize),
+    Continue(usize),
+}
@@ -0,0 +1,782 @@
+use crate::arithmetic;
+use crate::brace;
+use crate::glob;
+use crate::parser::{Redirection, RedirectionTarget};
+use crate::pattern;
+use crate::pattern::Pattern;
+use crate::shell::Shell;
+use crate::tilde;
+use crate::variables::is_valid_name;
+use crate::word::{Param, ParamOp, ReplaceAnchor, Word, WordPart};
+use std::fmt;
+
+/// An expansion that can't go ahead, like `${VAR:?}` with `VAR` unset. The command it was part
+/// of doesn't run.
+#[derive(Debug)]
+pub struct ExpansionError {
+    pub subject: String,
+    pub message: String,
+}
+
+impl ExpansionError {
+    pub fn new(subject: impl fmt::Display, message: &str) -> Self {
+        Self {
+            subject: subject.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ExpansionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.subject, self.message)
+    }
+}
+
+/// A field as its plain text, plus the pattern it makes, where quoted text only matches itself.
+#[derive(Debug, Default)]
+struct Field {
+    text: String,
+    pattern: String,
+    // whether unquoted text brought in a wildcard, making this a pathname pattern
+    glob: bool,
+}
+
+/// Fields being built out of a word. A field only exists once something quoted or non-empty went
+/// into it, so an unquoted expansion of an empty variable disappears from the command line.
+#[derive(Default)]
+struct Fields {
+    done: Vec<Field>,
+    current: Field,
+    started: bool,
+    /// The characters unquoted expansions are split at, from `IFS`.
+    ifs: String,
+    // false where a word always stays one field, like an assignment's value
+    split: bool,
+    // what the split text so far ended in, which decides what a following delimiter does
+    delimiter: Delimiter,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Delimiter {
+    #[default]
+    None,
+    Whitespace,
+    Other,
+}
+
+impl Fields {
+    fn new(shell: &Shell) -> Self {
+        Self {
+            ifs: shell.vars.get("IFS").unwrap_or(" \t\n").to_string(),
+            split: true,
+            ..Self::default()
+        }
+    }
+
+    /// Fields that never split, so the word they're built from stays a single field.
+    fn unsplit() -> Self {
+        Self::default()
+    }
+
+    /// Adds the result of an expansion, which gets split into fields unless it's quoted.
+    fn push_expansion(&mut self, text: &str, quoted: bool) {
+        match quoted || !self.split {
+            true => self.push_str(text, quoted),
+            false => self.push_split(text),
+        }
+    }
+
+    /// Adds unquoted text that gets split into fields at `IFS` characters. Runs of `IFS`
+    /// whitespace only separate fields, while any other `IFS` character ends one even if that
+    /// leaves it empty, taking the whitespace around it along.
+    fn push_split(&mut self, text: &str) {
+        let mut run = String::new();
+        for c in text.chars() {
+            if !self.ifs.contains(c) {
+                run.push(c);
+                continue;
+            }
+            self.push_str(&std::mem::take(&mut run), false);
+            match (matches!(c, ' ' | '\t' | '\n'), self.delimiter) {
+                (true, _) => {
+                    if self.started {
+                        self.finish_field();
+                        self.delimiter = Delimiter::Whitespace;
+                    }
+                }
+                (false, Delimiter::Whitespace) => self.delimiter = Delimiter::Other,
+                (false, _) => {
+                    self.started = true;
+                    self.finish_field();
+                    self.delimiter = Delimiter::Other;
+                }
+            }
+        }
+        self.push_str(&run, false);
+    }
+
+    fn push_str(&mut self, text: &str, quoted: bool) {
+        self.current.text.push_str(text);
+        match quoted {
+            true => self.current.pattern.push_str(&pattern::escape(text)),
+            false => {
+                self.current.pattern.push_str(text);
+                self.current.glob |= text.contains(['*', '?', '[', '(']);
+            }
+        }
+        if !text.is_empty() {
+            self.started = true;
+            self.delimiter = Delimiter::None;
+        }
+    }
+
+    /// Marks the current field as existing even if it stays empty, as with `''`.
+    fn start(&mut self) {
+        self.started = true;
+        self.delimiter = Delimiter::None;
+    }
+
+    fn finish_field(&mut self) {
+        if self.started {
+            self.done.push(std::mem::take(&mut self.current));
+        }
+        self.current = Field::default();
+        self.started = false;
+    }
+
+    fn finish(mut self) -> Vec<Field> {
+        self.finish_field();
+        self.done
+    }
+}
+
+type Result<T> = std::result::Result<T, ExpansionError>;
+
+/// Expands the words of a command line into the fields it runs with.
+pub fn expand_words<'a>(
+    shell: &mut Shell,
+    words: impl IntoIterator<Item = &'a Word>,
+) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    for word in words {
+        fields.extend(expand_word(shell, word, false)?);
+    }
+    Ok(fields)
+}
+
+/// Expands a command's name and arguments into fields. The `NAME=value` arguments of `export`
+/// and `readonly` get tildes expanded like an assignment's value.
+pub fn expand_command<'a>(
+    shell: &mut Shell,
+    words: impl IntoIterator<Item = &'a Word>,
+) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut declaration = false;
+    for (i, word) in words.into_iter().enumerate() {
+        if i == 0 {
+            declaration = matches!(word.as_literal(), Some("export" | "readonly"));
+        }
+        fields.extend(expand_word(shell, word, declaration && i > 0)?);
+    }
+    Ok(fields)
+}
+
+/// Expands a word into fields: braces, then a tilde prefix, parameters and substitutions, then
+/// pathname patterns, which stay as they are when they match nothing.
+fn expand_word(shell: &mut Shell, word: &Word, argument: bool) -> Result<Vec<String>> {
+    let options = shell.options;
+    let mut fields = Vec::new();
+    for word in brace::expand(word) {
+        let word = match argument {
+            true => tilde::expand_argument(&word, &shell.vars),
+            false => tilde::expand(&word, &shell.vars, false),
+        };
+        fields.extend(expand_fields(shell, &word)?);
+    }
+    let mut expanded = Vec::new();
+    for field in fields {
+        if !field.glob || !pattern::has_wildcards(&field.pattern, options.extglob) {
+            expanded.push(field.text);
+            continue;
+        }
+        let paths = glob::expand(&field.pattern, &options);
+        match paths.is_empty() {
+            true if options.failglob => return Err(ExpansionError::new("no match", &field.text)),
+            true if options.nullglob => {}
+            true => expanded.push(field.text),
+            false => expanded.extend(paths),
+        }
+    }
+    Ok(expanded)
+}
+
+fn expand_fields(shell: &mut Shell, word: &Word) -> Result<Vec<Field>> {
+    let mut fields = Fields::new(shell);
+    expand_parts(shell, &word.parts, false, &mut fields)?;
+    Ok(fields.finish())
+}
+
+/// Expands a word into a single field without splitting or globbing it.
+fn expand_unsplit(shell: &mut Shell, word: &Word) -> Result<Field> {
+    let mut fields = Fields::unsplit();
+    expand_parts(shell, &word.parts, false, &mut fields)?;
+    Ok(fields.current)
+}
+
+/// Expands a word into a single string, for places like assignments and redirection targets
+/// where it can't turn into several words.
+pub fn expand_string(shell: &mut Shell, word: &Word) -> Result<String> {
+    Ok(expand_unsplit(shell, word)?.text)
+}
+
+/// Expands the value of a variable assignment, where a tilde can also follow any `:`.
+pub fn expand_assignment(shell: &mut Shell, value: &Word) -> Result<String> {
+    let value = tilde::expand(value, &shell.vars, true);
+    expand_string(shell, &value)
+}
+
+/// Expands a word without splitting or globbing it, as with the word after `case`.
+pub fn expand_case_word(shell: &mut Shell, word: &Word) -> Result<String> {
+    let word = tilde::expand(word, &shell.vars, false);
+    expand_string(shell, &word)
+}
+
+/// Expands a word into a [`Pattern`], with anything quoted matching literally.
+pub fn expand_pattern(shell: &mut Shell, word: &Word) -> Result<Pattern> {
+    let field = expand_unsplit(shell, word)?;
+    Ok(Pattern::new(
+        &field.pattern,
+        shell.options.pattern_matching(),
+    ))
+}
+
+pub fn expand_redirections(
+    shell: &mut Shell,
+    redirections: &[Redirection],
+) -> Result<Vec<Redirection<String>>> {
+    redirections
+        .iter()
+        .map(|redirection| expand_redirection(shell, redirection))
+        .collect()
+}
+
+fn expand_redirection(shell: &mut Shell, redirection: &Redirection) -> Result<Redirection<String>> {
+    let target = match &redirection.target {
+        RedirectionTarget::File(word) => {
+            let word = tilde::expand(word, &shell.vars, false);
+            RedirectionTarget::File(expand_string(shell, &word)?)
+        }
+        RedirectionTarget::Fd(fd) => RedirectionTarget::Fd(*fd),
+        RedirectionTarget::Close => RedirectionTarget::Close,
+        RedirectionTarget::HereDoc(word) => RedirectionTarget::HereDoc(expand_string(shell, word)?),
+    };
+    Ok(Redirection {
+        fd: redirection.fd,
+        direction: redirection.direction,
+        target,
+    })
+}
+
+fn expand_parts(
+    shell: &mut Shell,
+    parts: &[WordPart],
+    quoted: bool,
+    fields: &mut Fields,
+) -> Result<()> {
+    for part in parts {
+        match part {
+            WordPart::Literal(text) => fields.push_str(text, quoted),
+            WordPart::Escaped(c) => {
+                fields.start();
+                fields.push_str(&c.to_string(), true);
+            }
+            WordPart::SingleQuoted(text) => {
+                fields.start();
+                fields.push_str(text, true);
+            }
+            WordPart::DoubleQuoted(parts) => {
+                // "$@" with no positional parameters is no field at all rather than an empty one
+                let only_params = parts.iter().all(|part| {
+                    matches!(part, WordPart::Param(Param { name, op: ParamOp::Value }) if name == "@")
+                });
+                if !only_params || parts.is_empty() {
+                    fields.start();
+                }
+                expand_parts(shell, parts, true, fields)?;
+            }
+            WordPart::Param(param) => expand_param(shell, param, quoted, fields)?,
+            WordPart::Arithmetic(expression) => {
+                let value = evaluate_arithmetic(shell, expression)?;
+                fields.push_expansion(&value.to_string(), quoted);
+            }
+            WordPart::CommandSubst(source) => {
+                let output = shell.substitute_command(source);
+                fields.push_expansion(&output, quoted);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn expand_param(shell: &mut Shell, param: &Param, quoted: bool, fields: &mut Fields) -> Result<()> {
+    let name = param.name.as_str();
+    if !is_param_name(name) {
+        return Err(ExpansionError::new(param, "bad substitution"));
+    }
+    let value = param_value(shell, name);
+    // with a colon, the operators treat an empty value the same as an unset one
+    let set = |colon: bool| value.as_ref().filter(|value| !colon || !value.is_empty());
+
+    match &param.op {
+        // each positional parameter is a field of its own, even inside quotes, with the first
+        // and last ones joining the text around them
+        ParamOp::Value if fields.split && (name == "@" || (name == "*" && !quoted)) => {
+            for (i, param) in shell.positional.iter().enumerate() {
+                if i > 0 {
+                    fields.finish_field();
+                }
+                if quoted {
+                    fields.start();
+                }
+                fields.push_expansion(param, quoted);
+            }
+        }
+        ParamOp::Value => fields.push_expansion(&value.unwrap_or_default(), quoted),
+        ParamOp::Length => {
+            let length = match name {
+                "@" | "*" => shell.positional.len(),
+                _ => value.unwrap_or_default().chars().count(),
+            };
+            fields.push_expansion(&length.to_string(), quoted);
+        }
+        ParamOp::Default { colon, word } => match set(*colon) {
+            Some(value) => fields.push_expansion(value, quoted),
+            None => expand_operand(shell, word, quoted, fields)?,
+        },
+        ParamOp::Assign { colon, word } => match set(*colon) {
+            Some(value) => fields.push_expansion(value, quoted),
+            None => {
+                if !is_valid_name(name) {
+                    let subject = format!("${}", name);
+                    return Err(ExpansionError::new(subject, "cannot assign in this way"));
+                }
+                let value = expand_string(shell, word)?;
+                shell
+                    .vars
+                    .set(name, &value)
+                    .map_err(|e| ExpansionError::new(e.name, "readonly variable"))?;
+                fields.push_expansion(&value, quoted);
+            }
+        },
+        ParamOp::Error { colon, word } => match set(*colon) {
+            Some(value) => fields.push_expansion(value, quoted),
+            None => {
+                let message = match (expand_string(shell, word)?, colon) {
+                    (message, _) if !message.is_empty() => message,
+                    (_, true) => "parameter null or not set".to_string(),
+                    (_, false) => "parameter not set".to_string(),
+                };
+                return Err(ExpansionError::new(name, &message));
+            }
+        },
+        ParamOp::Alternative { colon, word } => {
+            if set(*colon).is_some() {
+                expand_operand(shell, word, quoted, fields)?;
+            }
+        }
+        ParamOp::RemovePrefix { longest, pattern } => {
+            let pattern = expand_pattern(shell, pattern)?;
+            let value = value.unwrap_or_default();
+            fields.push_expansion(remove_prefix(&value, &pattern, *longest), quoted);
+        }
+        ParamOp::RemoveSuffix { longest, pattern } => {
+            let pattern = expand_pattern(shell, pattern)?;
+            let value = value.unwrap_or_default();
+            fields.push_expansion(remove_suffix(&value, &pattern, *longest), quoted);
+        }
+        ParamOp::Replace {
+            anchor,
+            pattern,
+            replacement,
+        } => {
+            let pattern = expand_pattern(shell, pattern)?;
+            let replacement = expand_string(shell, replacement)?;
+            let value = value.unwrap_or_default();
+            fields.push_expansion(&replace(&value, &pattern, &replacement, *anchor), quoted);
+        }
+        ParamOp::Substring { offset, length } => {
+            let offset = evaluate_arithmetic(shell, offset)?;
+            let length = match length {
+                Some(length) => Some(evaluate_arithmetic(shell, length)?),
+                None => None,
+            };
+            let value = value.unwrap_or_default();
+            let substring = substring(&value, offset, length)
+                .ok_or_else(|| ExpansionError::new(param, "substring expression < 0"))?;
+            fields.push_expansion(&substring, quoted);
+        }
+    }
+    Ok(())
+}
+
+/// Expands the word standing in for a parameter, as in `${VAR:-word}`. Unquoted, its text gets
+/// split into fields like the value of a parameter would be.
+fn expand_operand(shell: &mut Shell, word: &Word, quoted: bool, fields: &mut Fields) -> Result<()> {
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(text) => fields.push_expansion(text, quoted),
+            part => expand_parts(shell, std::slice::from_ref(part), quoted, fields)?,
+        }
+    }
+    Ok(())
+}
+
+/// Expands `expression` and evaluates it, for `$((...))`, `((...))` and the offset and length in
+/// `${VAR:offset:length}`.
+pub fn evaluate_arithmetic(shell: &mut Shell, expression: &Word) -> Result<i64> {
+    let text = expand_string(shell, expression)?;
+    arithmetic::evaluate(&text, &mut shell.vars)
+        .map_err(|e| ExpansionError::new(e.expression, &e.message))
+}
+
+/// The value of a parameter, or `None` if it isn't set.
+fn param_value(shell: &Shell, name: &str) -> Option<String> {
+    match name {
+        "?" => Some(shell.last_status.to_string()),
+        "$" => Some(shell.pid.to_string()),
+        "!" => shell.last_background_pid.map(|pid| pid.to_string()),
+        "#" => Some(shell.positional.len().to_string()),
+        "0" => Some(shell.name.clone()),
+        // "$*" joins them with the first character of IFS
+        "*" => {
+            let separator = match shell.vars.get("IFS") {
+                Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                None => " ".to_string(),
+            };
+            Some(shell.positional.join(&separator))
+        }
+        "@" => Some(shell.positional.join(" ")),
+        _ => match name.parse::<usize>() {
+            Ok(n) => shell.positional.get(n.checked_sub(1)?).cloned(),
+            Err(_) => shell.vars.get(name).map(str::to_string),
+        },
+    }
+}
+
+/// Whether `name` can be expanded: a variable, a positional parameter or a special parameter.
+fn is_param_name(name: &str) -> bool {
+    is_valid_name(name)
+        || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit()))
+        || matches!(name, "?" | "$" | "!" | "#" | "@" | "*")
+}
+
+/// Byte offsets of every character boundary in `text`, including its end.
+fn boundaries(text: &str) -> Vec<usize> {
+    text.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect()
+}
+
+fn remove_prefix<'a>(value: &'a str, pattern: &Pattern, longest: bool) -> &'a str {
+    let mut ends = boundaries(value);
+    if longest {
+        ends.reverse();
+    }
+    match ends.into_iter().find(|&end| pattern.matches(&value[..end])) {
+        Some(end) => &value[end..],
+        None => value,
+    }
+}
+
+fn remove_suffix<'a>(value: &'a str, pattern: &Pattern, longest: bool) -> &'a str {
+    let mut starts = boundaries(value);
+    if !longest {
+        starts.reverse();
+    }
+    match starts
+        .into_iter()
+        .find(|&start| pattern.matches(&value[start..]))
+    {
+        Some(start) => &value[..start],
+        None => value,
+    }
+}
+
+/// Replaces the longest match of `pattern` in `value` according to `anchor`.
+fn replace(value: &str, pattern: &Pattern, replacement: &str, anchor: ReplaceAnchor) -> String {
+    let bounds = boundaries(value);
+    // the longest match starting at `start`, as the offset it ends at
+    let longest_match = |start: usize| {
+        bounds
+            .iter()
+            .rev()
+            .copied()
+            .take_while(|&end| end > start)
+            .find(|&end| pattern.matches(&value[start..end]))
+    };
+
+    match anchor {
+        // an anchored pattern can match nothing, which puts the replacement at that end
+        ReplaceAnchor::Start => match longest_match(0).or(pattern.matches("").then_some(0)) {
+            Some(end) => format!("{}{}", replacement, &value[end..]),
+            None => value.to_string(),
+        },
+        ReplaceAnchor::End => {
+            let start = bounds
+                .iter()
+                .copied()
+                .find(|&start| pattern.matches(&value[start..]));
+            match start {
+                Some(start) => format!("{}{}", &value[..start], replacement),
+                None => value.to_string(),
+            }
+        }
+        ReplaceAnchor::First | ReplaceAnchor::All if pattern.is_empty() => value.to_string(),
+        ReplaceAnchor::First | ReplaceAnchor::All => {
+            let mut result = String::new();
+            let mut copied = 0;
+            let mut i = 0;
+            while i < bounds.len() - 1 {
+                let start = bounds[i];
+                match longest_match(start) {
+                    Some(end) if start >= copied => {
+                        result.push_str(&value[copied..start]);
+                        result.push_str(replacement);
+                        copied = end;
+                        if anchor == ReplaceAnchor::First {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            result.push_str(&value[copied..]);
+            result
+        }
+    }
+}
+
+/// `${VAR:offset:length}`, counting in characters. A negative offset counts from the end, and so
+/// does a negative length, which then marks where the substring ends. `None` if that end comes
+/// before the start.
+fn substring(value: &str, offset: i64, length: Option<i64>) -> Option<String> {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len() as i64;
+    let start = match offset {
+        offset if offset < 0 => len.saturating_add(offset),
+        offset => offset,
+    };
+    if start < 0 || start > len {
+        return Some(String::new());
+    }
+    let end = match length {
+        None => len,
+        Some(length) if length < 0 => len.saturating_add(length),
+        Some(length) => start.saturating_add(length).min(len),
+    };
+    if end < start {
+        return None;
+    }
+    Some(chars[start as usize..end as usize].iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::Token;
+    use crate::lexer::Lexer;
+    use crate::pattern::MatchOptions;
+    use rustyline::Editor;
+
+    fn pattern(text: &str) -> Pattern {
+        Pattern::new(text, MatchOptions::default())
+    }
+
+    fn shell() -> Shell {
+        Shell::new(Editor::new().unwrap(), 0, "sh".to_string(), Vec::new())
+    }
+
+    /// The fields `source` expands to, as a command's arguments would.
+    fn expand(shell: &mut Shell, source: &str) -> Vec<String> {
+        let words: Vec<Word> = Lexer::new(source)
+            .lex()
+            .into_iter()
+            .filter_map(|spanned| match spanned.token {
+                Token::Word(word) => Some(word),
+                _ => None,
+            })
+            .collect();
+        expand_words(shell, &words).unwrap()
+    }
+
+    #[test]
+    fn removes_shortest_and_longest_prefix() {
+        // ${x#*/} and ${x##*/}
+        assert_eq!(remove_prefix("a/b/c", &pattern("*/"), false), "b/c");
+        assert_eq!(remove_prefix("a/b/c", &pattern("*/"), true), "c");
+        assert_eq!(remove_prefix("a/b/c", &pattern("x*"), true), "a/b/c");
+    }
+
+    #[test]
+    fn removes_shortest_and_longest_suffix() {
+        // ${x%.*} and ${x%%.*}
+        assert_eq!(remove_suffix("f.tar.gz", &pattern(".*"), false), "f.tar");
+        assert_eq!(remove_suffix("f.tar.gz", &pattern(".*"), true), "f");
+        assert_eq!(
+            remove_suffix("f.tar.gz", &pattern("[0-9]"), true),
+            "f.tar.gz"
+        );
+    }
+
+    #[test]
+    fn empty_pattern_removes_nothing() {
+        assert_eq!(remove_prefix("abc", &pattern(""), true), "abc");
+        assert_eq!(remove_suffix("abc", &pattern(""), true), "abc");
+    }
+
+    #[test]
+    fn replaces_the_longest_match() {
+        assert_eq!(
+            replace("aaa", &pattern("a"), "b", ReplaceAnchor::First),
+            "baa"
+        );
+        assert_eq!(
+            replace("aaa", &pattern("a"), "b", ReplaceAnchor::All),
+            "bbb"
+        );
+        assert_eq!(
+            replace("abab", &pattern("a*"), "x", ReplaceAnchor::First),
+            "x"
+        );
+        assert_eq!(
+            replace("a.b.c", &pattern("."), "", ReplaceAnchor::All),
+            "abc"
+        );
+        assert_eq!(
+            replace("aaa", &pattern("c"), "b", ReplaceAnchor::All),
+            "aaa"
+        );
+    }
+
+    #[test]
+    fn replaces_anchored_matches() {
+        assert_eq!(
+            replace("aba", &pattern("a"), "x", ReplaceAnchor::Start),
+            "xba"
+        );
+        assert_eq!(
+            replace("aba", &pattern("a"), "x", ReplaceAnchor::End),
+            "abx"
+        );
+        assert_eq!(
+            replace("aba", &pattern("b"), "x", ReplaceAnchor::Start),
+            "aba"
+        );
+        assert_eq!(
+            replace("aba", &pattern("b"), "x", ReplaceAnchor::End),
+            "aba"
+        );
+    }
+
+    #[test]
+    fn empty_patterns_replace_only_when_anchored() {
+        // ${x/#/p} and ${x/%/s} add to the ends, while ${x//} leaves the value alone
+        let empty = pattern("");
+        assert_eq!(replace("abc", &empty, "p", ReplaceAnchor::Start), "pabc");
+        assert_eq!(replace("abc", &empty, "s", ReplaceAnchor::End), "abcs");
+        assert_eq!(replace("", &empty, "p", ReplaceAnchor::Start), "p");
+        assert_eq!(replace("abc", &empty, "x", ReplaceAnchor::First), "abc");
+        assert_eq!(replace("abc", &empty, "x", ReplaceAnchor::All), "abc");
+        // so does an anchored pattern that matches nothing
+        assert_eq!(replace("abc", &pattern("*"), "x", ReplaceAnchor::End), "x");
+        assert_eq!(replace("", &pattern("*"), "x", ReplaceAnchor::End), "x");
+    }
+
+    #[test]
+    fn takes_substrings_by_offset_and_length() {
+        let sub = |offset, length| substring("abcdef", offset, length);
+        assert_eq!(sub(2, None).as_deref(), Some("cdef"));
+        assert_eq!(sub(1, Some(3)).as_deref(), Some("bcd"));
+        assert_eq!(sub(-2, None).as_deref(), Some("ef"));
+        assert_eq!(sub(1, Some(-2)).as_deref(), Some("bcd"));
+        assert_eq!(sub(10, None).as_deref(), Some(""));
+        assert_eq!(sub(-10, None).as_deref(), Some(""));
+        // a negative length that ends before the offset is an error
+        assert_eq!(sub(4, Some(-3)), None);
+    }
+
+    #[test]
+    fn substrings_with_huge_offsets_and_lengths_dont_overflow() {
+        let sub = |offset, length| substring("abcdef", offset, length);
+        assert_eq!(sub(i64::MAX, None).as_deref(), Some(""));
+        assert_eq!(sub(i64::MIN, None).as_deref(), Some(""));
+        assert_eq!(sub(i64::MAX, Some(i64::MAX)).as_deref(), Some(""));
+        assert_eq!(sub(2, Some(i64::MAX)).as_deref(), Some("cdef"));
+        assert_eq!(sub(-1, Some(i64::MAX)).as_deref(), Some("f"));
+        assert_eq!(sub(0, Some(i64::MIN)), None);
+        assert_eq!(sub(i64::MIN, Some(i64::MIN)).as_deref(), Some(""));
+    }
+
+    fn split(ifs: &str, text: &str) -> Vec<String> {
+        let mut fields = Fields {
+            ifs: ifs.to_string(),
+            split: true,
+            ..Fields::default()
+        };
+        fields.push_expansion(text, false);
+        fields
+            .finish()
+            .into_iter()
+            .map(|field| field.text)
+            .collect()
+    }
+
+    #[test]
+    fn splits_at_non_whitespace_ifs() {
+        assert_eq!(split(":", "a::b:"), ["a", "", "b"]);
+        assert_eq!(split(":", ":a"), ["", "a"]);
+    }
+
+    #[test]
+    fn whitespace_around_other_ifs_characters_is_absorbed() {
+        assert_eq!(split(" :", " a : b "), ["a", "b"]);
+        assert_eq!(split(" :", "a  :  :b"), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn empty_ifs_and_unsplit_fields_stay_whole() {
+        assert_eq!(split("", "a b"), ["a b"]);
+        let mut fields = Fields::unsplit();
+        fields.push_expansion("a b:c", false);
+        assert_eq!(fields.finish().len(), 1);
+    }
+
+    #[test]
+    fn split_text_joins_the_words_around_it() {
+        let mut fields = Fields {
+            ifs: " \t\n".to_string(),
+            split: true,
+            ..Fields::default()
+        };
+        fields.push_str("a", true);
+        fields.push_expansion(" b\tc\n", false);
+        let texts: Vec<String> = fields.finish().into_iter().map(|f| f.text).collect();
+        assert_eq!(texts, ["a", "b", "c"]);
+
+        assert!(split(" \t\n", "  ").is_empty());
+    }
+
+    #[test]
+    fn splits_unquoted_operands_into_fields() {
+        let mut shell = shell();
+        shell.vars.set("IFS", " \t\n").unwrap();
+        shell.vars.set("v", "set").unwrap();
+        assert_eq!(expand(&mut shell, "${never_set:-a b}"), ["a", "b"]);
+        assert_eq!(expand(&mut shell, "\"${never_set:-a b}\""), ["a b"]);
+        assert_eq!(expand(&mut shell, "${never_set:-'a b' c}"), ["a b", "c"]);
+        assert_eq!(expand(&mut shell, "${v:+x  y}"), ["x", "y"]);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::options::ShellOptions;
+use crate::pattern;
+use crate::pattern::Pattern;
+use std::fs;
+
+/// Expands a pathname pattern like `src/*.rs` into the paths it matches, sorted. Each `/`
+/// separated component is matched against directory entries on its own, and a leading `.` in
+/// a name has to be matched explicitly unless `dotglob` is set. With `globstar`, a `**`
+/// component matches any number of directories.
+pub fn expand(pattern: &str, options: &ShellOptions) -> Vec<String> {
+    // every path found so far, ending in a `/` unless it's the empty start of a relative path
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        let mut next = Vec::new();
+        for base in &paths {
+            if component.is_empty() {
+                // `a//b` or a trailing slash, which only matches directories
+                next.push(base.clone());
+            } else if options.globstar && *component == "**" {
+                // on its own, `**` matches every file below; before a `/`, only directories
+                if !last {
+                    next.push(base.clone());
+                }
+                descendants(base, !last, options.dotglob, &mut next);
+            } else if !pattern::has_wildcards(component, options.extglob) {
+                next.push(format!("{}{}", base, pattern::unescape(component)));
+            } else {
+                next.extend(
+                    matching_entries(base, component, options)
+                        .into_iter()
+                        .map(|name| format!("{}{}", base, name)),
+                );
+            }
+        }
+        if !last {
+            for path in &mut next {
+                if !path.is_empty() && !path.ends_with('/') {
+                    path.push('/');
+                }
+            }
+        }
+        paths = next;
+    }
+
+    // components without wildcards weren't checked as we went
+    let mut paths: Vec<String> = paths
+        .into_iter()
+        .filter(|path| !path.is_empty() && fs::symlink_metadata(path).is_ok())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Names in directory `base` that match the pattern `component`, sorted.
+fn matching_entries(base: &str, component: &str, options: &ShellOptions) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir_path(base)) else {
+        return Vec::new();
+    };
+    let hidden_allowed = options.dotglob || component.starts_with('.');
+    let pattern = Pattern::new(component, options.glob_matching());
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| hidden_allowed || !name.starts_with('.'))
+        .filter(|name| pattern.matches(name))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Adds every path below directory `base` to `paths`, or only the directories if `dirs_only`.
+/// Symbolic links to directories are listed but not descended into.
+fn descendants(base: &str, dirs_only: bool, dotglob: bool, paths: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir_path(base)) else {
+        return;
+    };
+    let mut entries: Vec<(String, bool)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let is_dir = entry.file_type().ok()?.is_dir();
+            Some((entry.file_name().into_string().ok()?, is_dir))
+        })
+        .filter(|(name, _)| dotglob || !name.starts_with('.'))
+        .collect();
+    entries.sort();
+    for (name, is_dir) in entries {
+        let path = format!("{}{}", base, name);
+        if is_dir {
+            paths.push(path.clone());
+            descendants(&format!("{}/", path), dirs_only, dotglob, paths);
+        } else if !dirs_only {
+            paths.push(path);
+        }
+    }
+}
+
+fn dir_path(base: &str) -> &str {
+    match base {
+        "" => ".",
+        base => base,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory tree for patterns to match, under the system's temporary directory.
+    fn tree(name: &str, files: &[&str]) -> String {
+        let dir = std::env::temp_dir().join(format!("sh-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir.to_str().unwrap().to_string()
+    }
+
+    fn names(dir: &str, pattern: &str) -> Vec<String> {
+        names_with(dir, pattern, ShellOptions::default())
+    }
+
+    fn names_with(dir: &str, pattern: &str, options: ShellOptions) -> Vec<String> {
+        expand(&format!("{}/{}", dir, pattern), &options)
+            .into_iter()
+            .map(|path| path[dir.len() + 1..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn matches_names_in_sorted_order() {
+        let dir = tree("sorted", &["b.rs", "a.rs", "c.txt"]);
+        assert_eq!(names(&dir, "*.rs"), ["a.rs", "b.rs"]);
+        assert_eq!(names(&dir, "?.*"), ["a.rs", "b.rs", "c.txt"]);
+        assert!(names(&dir, "*.md").is_empty());
+    }
+
+    #[test]
+    fn matches_each_component_of_a_path() {
+        let dir = tree("components", &["src/main.rs", "src/x/lib.rs", "tests/a.rs"]);
+        assert_eq!(names(&dir, "*/*.rs"), ["src/main.rs", "tests/a.rs"]);
+        assert_eq!(names(&dir, "src/*/"), ["src/x/"]);
+        assert_eq!(names(&dir, "*/x/lib.rs"), ["src/x/lib.rs"]);
+    }
+
+    #[test]
+    fn hidden_names_need_a_leading_dot() {
+        let dir = tree("hidden", &[".env", "env"]);
+        assert_eq!(names(&dir, "*env"), ["env"]);
+        assert_eq!(names(&dir, ".*"), [".env"]);
+    }
+
+    #[test]
+    fn escaped_wildcards_match_literally() {
+        let dir = tree("escaped", &["a*", "ab"]);
+        assert_eq!(names(&dir, "a\\*"), ["a*"]);
+        assert_eq!(names(&dir, "a*"), ["a*", "ab"]);
+    }
+
+    #[test]
+    fn dotglob_matches_hidden_names() {
+        let dir = tree("dotglob", &[".env", "env"]);
+        let options = ShellOptions {
+            dotglob: true,
+            ..ShellOptions::default()
+        };
+        assert_eq!(names_with(&dir, "*env", options), [".env", "env"]);
+    }
+
+    #[test]
+    fn globstar_matches_any_depth() {
+        let dir = tree(
+            "globstar",
+            &["a.rs", "src/b.rs", "src/x/c.rs", "src/x/d.txt"],
+        );
+        let options = ShellOptions {
+            globstar: true,
+            ..ShellOptions::default()
+        };
+        assert_eq!(
+            names_with(&dir, "**/*.rs", options),
+            ["a.rs", "src/b.rs", "src/x/c.rs"]
+        );
+        // the directory the pattern starts in counts too, as in bash
+        assert_eq!(names_with(&dir, "**/", options), ["", "src/", "src/x/"]);
+        // without globstar, ** is just *
+        assert_eq!(names(&dir, "**/*.rs"), ["src/b.rs"]);
+    }
+
+    #[test]
+    fn nocaseglob_ignores_case() {
+        let dir = tree("nocaseglob", &["README.md", "notes.MD"]);
+        let options = ShellOptions {
+            nocaseglob: true,
+            ..ShellOptions::default()
+        };
+        assert_eq!(names_with(&dir, "*.md", options), ["README.md", "notes.MD"]);
+        assert_eq!(names(&dir, "*.md"), ["README.md"]);
+    }
+
+    #[test]
+    fn extglob_groups_match_names() {
+        let dir = tree("extglob", &["a.c", "a.h", "a.o"]);
+        let options = ShellOptions {
+            extglob: true,
+            ..ShellOptions::default()
+        };
+        assert_eq!(names_with(&dir, "a.@(c|h)", options), ["a.c", "a.h"]);
+        assert_eq!(names_with(&dir, "!(*.o)", options), ["a.c", "a.h"]);
+    }
+}
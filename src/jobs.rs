@@ -0,0 +1,379 @@
+use crate::util;
+use nix::errno::Errno;
+use nix::sys::signal::{kill, killpg, signal, SigHandler, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{getpgrp, getpid, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid};
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+
+/// Signals an interactive shell ignores. Keyboard signals are meant for the foreground job, and
+/// job control signals would otherwise stop the shell itself.
+const SHELL_IGNORED_SIGNALS: [Signal; 5] = [
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTSTP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+}
+
+/// A pipeline running in its own process group.
+#[derive(Debug)]
+pub struct Job {
+    /// Job number, or 0 for a foreground job that never made it into the table.
+    pub id: usize,
+    pub pgid: Pid,
+    /// Processes that haven't been reaped yet.
+    pub pids: Vec<Pid>,
+    pub command: String,
+    pub state: JobState,
+    /// Exit status of the last process, once it has been reaped.
+    status: Option<i32>,
+}
+
+impl Job {
+    pub fn new(pgid: Pid, pids: Vec<Pid>, command: String) -> Self {
+        Self {
+            id: 0,
+            pgid,
+            pids,
+            command,
+            state: JobState::Running,
+            status: None,
+        }
+    }
+
+    /// Records a wait status for one of the job's processes.
+    fn update(&mut self, pid: Pid, status: WaitStatus) {
+        let last = self.pids.last() == Some(&pid);
+        match status {
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                self.pids.retain(|&p| p != pid);
+                if last {
+                    self.status = Some(util::wait_status(Ok(status)));
+                }
+            }
+            WaitStatus::Stopped(..) => self.state = JobState::Stopped,
+            WaitStatus::Continued(_) => self.state = JobState::Running,
+            _ => {}
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pids.is_empty()
+    }
+
+    fn exit_status(&self) -> i32 {
+        self.status.unwrap_or(0)
+    }
+}
+
+/// How a freshly forked process joins job control, before it runs anything.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessGroup {
+    /// The group to join, or `None` to lead a new one.
+    pub pgid: Option<Pid>,
+    /// The terminal to hand to the group if it's a foreground job.
+    pub terminal: Option<RawFd>,
+}
+
+impl ProcessGroup {
+    /// Called in the parent with the child it just forked. The child joins the group itself
+    /// too; whichever of the two runs first, the group exists before either relies on it.
+    pub fn admit(self, child: Pid) {
+        let _ = setpgid(child, self.pgid.unwrap_or(child));
+    }
+
+    /// Called in the child. Only async-signal-safe calls happen here, so it can run in
+    /// `pre_exec`.
+    pub fn join(self) -> nix::Result<()> {
+        let pgid = self.pgid.unwrap_or_else(getpid);
+        setpgid(Pid::from_raw(0), pgid)?;
+        if let Some(terminal) = self.terminal {
+            // SAFETY: the descriptor belongs to the shell and outlives the child
+            tcsetpgrp(unsafe { BorrowedFd::borrow_raw(terminal) }, pgid)?;
+        }
+        reset_signals()
+    }
+}
+
+/// Undoes the shell ignoring keyboard and job control signals, so a child is interruptible and
+/// stoppable again. Async-signal-safe.
+pub fn reset_signals() -> nix::Result<()> {
+    for sig in SHELL_IGNORED_SIGNALS {
+        // SAFETY: resetting to the default handler doesn't run any code
+        unsafe { signal(sig, SigHandler::SigDfl)? };
+    }
+    Ok(())
+}
+
+/// Jobs that are running in the background or stopped, plus the state needed for job control.
+/// Job control is only enabled for an interactive shell; otherwise jobs share the shell's
+/// process group and the terminal is never handed over.
+#[derive(Debug, Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    /// The controlling terminal, if the shell has job control.
+    terminal: Option<OwnedFd>,
+    shell_pgid: Option<Pid>,
+}
+
+impl JobTable {
+    /// Takes control of the terminal if stdin is one.
+    pub fn new() -> Self {
+        let stdin = io::stdin();
+        if !isatty(stdin.as_fd()).unwrap_or(false) {
+            return Self::default();
+        }
+        let Ok(terminal) = stdin.as_fd().try_clone_to_owned() else {
+            return Self::default();
+        };
+
+        // wait until we're in the foreground, like any other job would have to
+        while tcgetpgrp(&terminal).is_ok_and(|pgid| pgid != getpgrp()) {
+            let _ = killpg(getpgrp(), Signal::SIGTTIN);
+        }
+        for sig in SHELL_IGNORED_SIGNALS {
+            // SAFETY: ignoring a signal doesn't run any code
+            let _ = unsafe { signal(sig, SigHandler::SigIgn) };
+        }
+        // fails harmlessly if we're already a session or group leader
+        let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+        let shell_pgid = getpgrp();
+        let _ = tcsetpgrp(&terminal, shell_pgid);
+
+        Self {
+            jobs: Vec::new(),
+            terminal: Some(terminal),
+            shell_pgid: Some(shell_pgid),
+        }
+    }
+
+    /// A table for a forked subshell, which doesn't do job control of its own.
+    pub fn subshell() -> Self {
+        Self::default()
+    }
+
+    pub fn has_job_control(&self) -> bool {
+        self.shell_pgid.is_some()
+    }
+
+    /// How a new process should set itself up: joining `pgid`, or leading a new group if it's
+    /// `None`. Returns `None` without job control, where processes stay in the shell's group.
+    pub fn process_group(&self, pgid: Option<Pid>, foreground: bool) -> Option<ProcessGroup> {
+        self.shell_pgid?;
+        let terminal = match foreground {
+            true => self.terminal.as_ref().map(|fd| fd.as_raw_fd()),
+            false => None,
+        };
+        Some(ProcessGroup { pgid, terminal })
+    }
+
+    /// Adds a job and returns its job number.
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    /// Sends `signal` to every process of a job: its whole process group with job control, or
+    /// each of its processes without.
+    pub fn signal(&self, id: usize, signal: Signal) -> nix::Result<()> {
+        let Some(job) = self.jobs.iter().find(|job| job.id == id) else {
+            return Err(Errno::ESRCH);
+        };
+        self.signal_job(job, signal)
+    }
+
+    fn signal_job(&self, job: &Job, signal: Signal) -> nix::Result<()> {
+        match self.has_job_control() {
+            true => killpg(job.pgid, signal),
+            false => job.pids.iter().try_for_each(|&pid| kill(pid, signal)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    /// Resolves a job spec like `%2`, `%%`, `%+`, `%-` or `%prefix` to a job number. No spec
+    /// means the current job.
+    pub fn find(&self, spec: Option<&str>) -> Option<usize> {
+        let spec = spec.map(|spec| spec.strip_prefix('%').unwrap_or(spec));
+        let job = match spec {
+            None | Some("%" | "+" | "") => self.jobs.last(),
+            Some("-") => self.jobs.iter().rev().nth(1),
+            Some(spec) => match spec.parse::<usize>() {
+                Ok(id) => self.jobs.iter().find(|job| job.id == id),
+                Err(_) => self
+                    .jobs
+                    .iter()
+                    .rev()
+                    .find(|job| job.command.starts_with(spec)),
+            },
+        };
+        job.map(|job| job.id)
+    }
+
+    /// Gives the terminal to `job` and waits until it finishes or stops. A stopped job goes back
+    /// into the table. Returns the exit status of the job's last process, or 128 plus the
+    /// stopping signal.
+    pub fn wait_foreground(&mut self, job: Job) -> i32 {
+        if let Some(terminal) = &self.terminal {
+            let _ = tcsetpgrp(terminal, job.pgid);
+        }
+        self.wait_for(job)
+    }
+
+    /// Resumes a stopped or background job in the foreground and waits for it like
+    /// [`wait_foreground`](Self::wait_foreground). The job gets the terminal before it continues,
+    /// so it can't stop again on SIGTTIN or SIGTTOU.
+    pub fn continue_foreground(&mut self, mut job: Job) -> i32 {
+        if let Some(terminal) = &self.terminal {
+            let _ = tcsetpgrp(terminal, job.pgid);
+        }
+        let _ = self.signal_job(&job, Signal::SIGCONT);
+        job.state = JobState::Running;
+        self.wait_for(job)
+    }
+
+    fn wait_for(&mut self, mut job: Job) -> i32 {
+        let mut stopped = None;
+        while let Some(&pid) = job.pids.first() {
+            match waitpid(pid, Some(WaitPidFlag::WUNTRACED)) {
+                Ok(status @ WaitStatus::Stopped(_, signal)) => {
+                    job.update(pid, status);
+                    stopped = Some(signal);
+                    break;
+                }
+                Ok(status) => job.update(pid, status),
+                Err(_) => job.pids.retain(|&p| p != pid),
+            }
+        }
+
+        if let (Some(terminal), Some(shell_pgid)) = (&self.terminal, self.shell_pgid) {
+            let _ = tcsetpgrp(terminal, shell_pgid);
+        }
+
+        match stopped {
+            Some(signal) => {
+                let id = self.add(job);
+                let line = self.describe(id);
+                eprintln!("\n{}", line);
+                128 + signal as i32
+            }
+            None => {
+                let status = job.exit_status();
+                if self.has_job_control() {
+                    // the terminal echoed ^C or ^\ without a newline
+                    match Signal::try_from(status - 128) {
+                        Ok(Signal::SIGINT) => eprintln!(),
+                        Ok(Signal::SIGQUIT) => eprintln!("Quit"),
+                        _ => {}
+                    }
+                }
+                status
+            }
+        }
+    }
+
+    /// Reaps finished jobs without blocking, returning the lines announcing them, like
+    /// `[1]+  Done                    sleep 1`.
+    pub fn reap(&mut self) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut i = 0;
+        while i < self.jobs.len() {
+            let was_stopped = self.jobs[i].state == JobState::Stopped;
+            let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+            for pid in self.jobs[i].pids.clone() {
+                match waitpid(pid, Some(flags)) {
+                    Ok(WaitStatus::StillAlive) => {}
+                    Ok(status) => self.jobs[i].update(pid, status),
+                    // already reaped elsewhere
+                    Err(_) => self.jobs[i].pids.retain(|&p| p != pid),
+                }
+            }
+
+            let job = &self.jobs[i];
+            if job.is_done() {
+                messages.push(self.describe(job.id));
+                self.jobs.remove(i);
+                continue;
+            }
+            if job.state == JobState::Stopped && !was_stopped {
+                messages.push(self.describe(job.id));
+            }
+            i += 1;
+        }
+        messages
+    }
+
+    /// A `jobs`-style line for a job.
+    pub fn describe(&self, id: usize) -> String {
+        let Some(index) = self.jobs.iter().position(|job| job.id == id) else {
+            return String::new();
+        };
+        let job = &self.jobs[index];
+        let status = match (job.is_done(), job.state) {
+            (true, _) => match job.status {
+                Some(0) | None => "Done".to_string(),
+                Some(code) if code > 128 => signal_description(code - 128),
+                Some(code) => format!("Exit {}", code),
+            },
+            (false, JobState::Running) => "Running".to_string(),
+            (false, JobState::Stopped) => "Stopped".to_string(),
+        };
+        let suffix = match (job.is_done(), job.state) {
+            (false, JobState::Running) => " &",
+            _ => "",
+        };
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            job.id,
+            self.mark(index),
+            status,
+            job.command,
+            suffix
+        )
+    }
+
+    /// `+` for the current (most recent) job and `-` for the one before it.
+    fn mark(&self, index: usize) -> char {
+        match self.jobs.len() - index {
+            1 => '+',
+            2 => '-',
+            _ => ' ',
+        }
+    }
+}
+
+fn signal_description(signal: i32) -> String {
+    match Signal::try_from(signal) {
+        Ok(Signal::SIGTERM) => "Terminated".to_string(),
+        Ok(Signal::SIGKILL) => "Killed".to_string(),
+        Ok(Signal::SIGINT) => "Interrupt".to_string(),
+        Ok(Signal::SIGHUP) => "Hangup".to_string(),
+        Ok(Signal::SIGSEGV) => "Segmentation fault".to_string(),
+        Ok(Signal::SIGABRT) => "Aborted".to_string(),
+        Ok(Signal::SIGPIPE) => "Broken pipe".to_string(),
+        Ok(signal) => signal.as_str().to_string(),
+        Err(_) => format!("Signal {}", signal),
+    }
+}
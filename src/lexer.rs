@@ -1,18 +1,64 @@
-use crate::enums::{Operator, Token};
+use crate::enums::{Operator, Reserved, Span, SpannedToken, Token};
+use crate::word::{Param, ParamOp, ReplaceAnchor, Word, WordPart};
 
 #[derive(Debug, Clone)]
 enum LexerState {
     Normal,
     InSingleQuote,
     InDoubleQuote,
+    InHereDoc, // the body of an unquoted here-document, which expands like "..." minus the quotes
+}
+
+/// A here-document whose body starts on the line after the operator.
+#[derive(Debug)]
+struct PendingHereDoc {
+    token_index: usize,
+    delimiter: String,
+    // quoting any part of the delimiter turns off expansion in the body
+    quoted: bool,
+    strip_tabs: bool,
+}
+
+/// Where a `case` command being lexed is, which decides what its words are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseState {
+    Word, // the word being matched
+    In,   // the `in` after it
+    // patterns up to a `)`, where `esac` ends the command if it starts the first pattern
+    Pattern { start: bool },
+    Body, // commands up to `;;` or `esac`
 }
 
 pub struct Lexer {
     input: Vec<char>,
     position: usize,
     current_state: LexerState,
-    current_token: String,
-    tokens: Vec<Token>,
+    current_token: Vec<WordPart>,
+    current_token_quoted: bool,
+    // the text of the quotes currently open, added to current_token once they close
+    quoted_text: String,
+    quoted_parts: Vec<WordPart>,
+    tokens: Vec<SpannedToken>,
+    // where the token being built started, as an index into input
+    token_start: usize,
+    // Some(strip_tabs) if the next word is a here-document delimiter
+    heredoc_delimiter: Option<bool>,
+    pending_heredocs: Vec<PendingHereDoc>,
+    // lexing the word inside ${...}, which is never split into several tokens
+    in_operand: bool,
+    // the input stopped partway through a quote, a substitution or a line continuation
+    incomplete: bool,
+    // whether the next word is where a command starts, so it may be a reserved word
+    command_start: bool,
+    // compound commands like `if` and `while` that haven't reached their `fi` or `done` yet
+    open_compounds: usize,
+    // the `case` commands being lexed, innermost last
+    cases: Vec<CaseState>,
+    // `(`s that a `)` hasn't closed yet, not counting those around case patterns
+    paren_depth: usize,
+    // Some once lexing the source of `$(...)` reaches the `)` that ends it
+    substitution_end: Option<usize>,
+    in_substitution: bool,
 }
 
 impl Lexer {
@@ -21,132 +67,575 @@ impl Lexer {
             input: input.chars().collect(),
             position: 0,
             current_state: LexerState::Normal,
-            current_token: String::new(),
+            current_token: Vec::new(),
+            current_token_quoted: false,
+            quoted_text: String::new(),
+            quoted_parts: Vec::new(),
             tokens: Vec::new(),
+            token_start: 0,
+            heredoc_delimiter: None,
+            pending_heredocs: Vec::new(),
+            in_operand: false,
+            incomplete: false,
+            command_start: true,
+            open_compounds: 0,
+            cases: Vec::new(),
+            paren_depth: 0,
+            substitution_end: None,
+            in_substitution: false,
         }
     }
 
-    pub fn lex(&mut self) -> Vec<Token> {
-        while self.position < self.input.len() {
+    /// Whether the command the input started isn't finished yet and continues on the next line:
+    /// a quote or substitution is still open, the input ends in a `\`, `|`, `&&` or `||`, a
+    /// here-document is waiting for its delimiter line, or a compound command isn't closed.
+    pub fn needs_more_input(&self) -> bool {
+        self.incomplete || !self.pending_heredocs.is_empty() || self.open_compounds > 0
+    }
+
+    pub fn lex(&mut self) -> Vec<SpannedToken> {
+        while self.position < self.input.len() && self.substitution_end.is_none() {
             let c = self.input[self.position];
+            let between_tokens = self.current_token.is_empty() && !self.current_token_quoted;
+            if between_tokens && matches!(self.current_state, LexerState::Normal) {
+                self.token_start = self.position;
+            }
             self.handle_char(c);
             self.advance();
         }
 
-        if !self.current_token.is_empty() {
-            self.emit_token();
-        }
+        self.close_quotes();
+        self.emit_token();
 
+        // blank lines after a `|` still leave it waiting for a command
+        let last = self
+            .tokens
+            .iter()
+            .map(|spanned| &spanned.token)
+            .rfind(|token| !matches!(token, Token::Operator(Operator::Newline)));
+        if let Some(Token::Operator(Operator::Pipe | Operator::And | Operator::Or)) = last {
+            self.incomplete = true;
+        }
         std::mem::take(&mut self.tokens)
     }
 
+    /// An unterminated quote runs to the end of the input.
+    fn close_quotes(&mut self) {
+        self.incomplete |= matches!(
+            self.current_state,
+            LexerState::InSingleQuote | LexerState::InDoubleQuote
+        );
+        match self.current_state {
+            LexerState::InSingleQuote => {
+                let text = std::mem::take(&mut self.quoted_text);
+                self.current_token.push(WordPart::SingleQuoted(text));
+            }
+            LexerState::InDoubleQuote => {
+                let parts = std::mem::take(&mut self.quoted_parts);
+                self.current_token.push(WordPart::DoubleQuoted(parts));
+            }
+            LexerState::Normal | LexerState::InHereDoc => {}
+        }
+        self.current_state = LexerState::Normal;
+    }
+
+    /// Splits the body of an unquoted here-document into the parameters it expands and the text
+    /// around them.
+    fn lex_heredoc_body(body: &str) -> Word {
+        let mut lexer = Lexer::new(body);
+        lexer.current_state = LexerState::InHereDoc;
+        while lexer.position < lexer.input.len() {
+            let c = lexer.input[lexer.position];
+            lexer.handle_char(c);
+            lexer.advance();
+        }
+        Word {
+            parts: vec![WordPart::DoubleQuoted(lexer.quoted_parts)],
+        }
+    }
+
     fn handle_char(&mut self, c: char) {
-        // println!("{}, {}, {:?}", c, self.current_token, self.current_state);
+        // println!("{}, {:?}, {:?}", c, self.current_token, self.current_state);
         match self.current_state {
             LexerState::Normal => self.handle_normal_char(c),
             LexerState::InSingleQuote => self.handle_single_quote_char(c),
-            LexerState::InDoubleQuote => self.handle_double_quote_char(c),
+            LexerState::InDoubleQuote | LexerState::InHereDoc => self.handle_double_quote_char(c),
         }
     }
 
     fn handle_normal_char(&mut self, c: char) {
+        if self.in_operand
+            && (c.is_whitespace() || matches!(c, ';' | '&' | '|' | '<' | '>' | '(' | ')'))
+        {
+            self.push_char(c);
+            return;
+        }
         match c {
+            '\n' => {
+                self.emit_token();
+                self.push_char(c);
+                self.emit_token();
+                self.read_heredoc_bodies();
+            }
+            ';' => {
+                self.emit_token();
+                self.push_char(c);
+                if let Some(';') = self.peek() {
+                    self.advance();
+                    self.push_char(c);
+                }
+                self.emit_token();
+            }
             c if c.is_whitespace() => {
                 self.emit_token();
             }
             '&' => {
                 self.emit_token();
-                self.current_token.push(c);
-                if let Some('&') = self.peek() {
-                    self.advance();
-                    self.current_token.push(c);
+                self.push_char(c);
+                match self.peek() {
+                    Some('&') => {
+                        self.advance();
+                        self.push_char(c);
+                    }
+                    Some('>') => {
+                        // &> or &>>
+                        self.advance();
+                        self.push_char('>');
+                        if let Some('>') = self.peek() {
+                            self.advance();
+                            self.push_char('>');
+                        }
+                    }
+                    _ => {}
                 }
                 self.emit_token();
             }
 
             '|' => {
                 self.emit_token();
-                self.current_token.push(c);
+                self.push_char(c);
                 if let Some('|') = self.peek() {
                     self.advance();
-                    self.current_token.push(c);
+                    self.push_char(c);
                 }
                 self.emit_token();
             }
 
-            '>' => {
-                if !self.current_token.is_empty()
-                    && self
-                        .current_token
-                        .chars()
-                        .all(|ch| char::is_ascii_digit(&ch))
-                {
-                    // 1> or 2>>
-                    self.current_token.push(c);
-                    if let Some('>') = self.peek() {
-                        self.advance();
-                        self.current_token.push(c);
-                    }
-                    self.emit_token();
-                } else {
+            '>' | '<' => {
+                // digits directly before the operator name the fd it applies to, as in 2> or 1>>
+                let is_fd = self
+                    .current_literal()
+                    .is_some_and(|text| text.chars().all(|ch| ch.is_ascii_digit()));
+                if !is_fd {
                     // word boundary -> start of new operator
                     self.emit_token();
-                    self.current_token.push(c);
-                    if let Some('>') = self.peek() {
+                }
+                self.push_char(c);
+                match self.peek() {
+                    Some('>') => {
+                        // >> appends, <> opens for reading and writing
                         self.advance();
-                        self.current_token.push(c);
+                        self.push_char('>');
                     }
-                    self.emit_token();
+                    Some('<') if c == '<' => {
+                        // << and <<- start here-documents, <<< a here-string
+                        self.advance();
+                        self.push_char('<');
+                        if let Some(&next @ ('-' | '<')) = self.peek() {
+                            self.advance();
+                            self.push_char(next);
+                        }
+                    }
+                    Some('&') => {
+                        // >& and <& duplicate or close the target fd
+                        self.advance();
+                        self.push_char('&');
+                    }
+                    _ => {}
                 }
+                self.emit_token();
             }
 
             '\'' => {
                 self.current_state = LexerState::InSingleQuote;
+                self.current_token_quoted = true;
             }
             '"' => {
                 self.current_state = LexerState::InDoubleQuote;
+                self.current_token_quoted = true;
             }
             '\\' => {
+                if let Some('\n') = self.peek() {
+                    // a line continuation, which disappears
+                    self.advance();
+                    return;
+                }
                 if let Some(&next) = self.peek() {
+                    self.current_token_quoted = true;
                     // in normal, backslash escapes everything
-                    self.current_token.push(next);
+                    self.current_token.push(WordPart::Escaped(next));
                     self.advance();
                     return;
                 }
-                self.current_token.push(c);
+                // a backslash ending the input continues the line
+                self.incomplete |= !self.in_operand;
+                self.push_char(c);
             }
-            _ => self.current_token.push(c),
+            '$' => match self.read_expansion() {
+                Some(part) => self.current_token.push(part),
+                None => self.push_char(c),
+            },
+            // ((expression)) at the start of a word is an arithmetic command
+            '(' if self.current_token.is_empty() && !self.current_token_quoted => {
+                match self.find_arithmetic(self.position) {
+                    Some(end) => {
+                        let expression: String =
+                            self.input[self.position + 2..end - 1].iter().collect();
+                        self.position = end;
+                        let expression = Self::lex_operand(&expression);
+                        self.push_token(Token::Arithmetic(expression), end + 1);
+                    }
+                    // otherwise it's an operator of its own, like the ( before a case pattern
+                    None => {
+                        self.push_char(c);
+                        self.emit_token();
+                    }
+                }
+            }
+            // an extglob group like @(a|b) is part of the word, `|` and all
+            '(' if self.ends_with_extglob_prefix() => {
+                match self.find_closing(self.position, '(', ')') {
+                    Some(end) => {
+                        for i in self.position..=end {
+                            self.push_char(self.input[i]);
+                        }
+                        self.position = end;
+                    }
+                    None => self.push_char(c),
+                }
+            }
+            '(' | ')' => {
+                // the word before may be the `esac` that decides what this `)` is
+                self.emit_token();
+                let closes_nothing = self.paren_depth == 0
+                    && !matches!(self.cases.last(), Some(CaseState::Pattern { .. }));
+                if c == ')' && self.in_substitution && closes_nothing {
+                    self.substitution_end = Some(self.position);
+                    return;
+                }
+                self.push_char(c);
+                self.emit_token();
+            }
+            '`' => match self.read_backquoted(false) {
+                Some(part) => self.current_token.push(part),
+                None => self.push_char(c),
+            },
+            _ => self.push_char(c),
         }
     }
     fn handle_single_quote_char(&mut self, c: char) {
-        // Single quotes don't have escape sequences
+        // Single quotes don't have escape sequences or expansions
         match c {
             '\'' => {
                 self.current_state = LexerState::Normal;
+                let text = std::mem::take(&mut self.quoted_text);
+                self.current_token.push(WordPart::SingleQuoted(text));
             }
-            _ => self.current_token.push(c),
+            _ => self.quoted_text.push(c),
         }
     }
 
     fn handle_double_quote_char(&mut self, c: char) {
+        let in_heredoc = matches!(self.current_state, LexerState::InHereDoc);
         match c {
-            '"' => {
+            '"' if !in_heredoc => {
                 self.current_state = LexerState::Normal;
+                let parts = std::mem::take(&mut self.quoted_parts);
+                self.current_token.push(WordPart::DoubleQuoted(parts));
             }
             '\\' => {
-                if let Some(next) = self.peek() {
-                    // if the next char is ' or \", escape those
-                    // else, add \
-                    let esc_chars = vec!['\"', '\\'];
-                    if esc_chars.contains(next) {
+                if let Some(&next) = self.peek() {
+                    if next == '\n' && !in_heredoc {
+                        self.advance();
+                        return;
+                    }
+                    // only characters that mean something inside the quotes can be escaped, the
+                    // backslash stays before anything else
+                    if matches!(next, '$' | '`' | '\\') || (next == '"' && !in_heredoc) {
                         self.advance();
-                        self.current_token.push(self.input[self.position]);
+                        self.quoted_parts.push(WordPart::Escaped(next));
                         return;
                     }
                 }
-                self.current_token.push(c);
+                self.push_char(c);
+            }
+            '$' => match self.read_expansion() {
+                Some(part) => self.quoted_parts.push(part),
+                None => self.push_char(c),
+            },
+            '`' => match self.read_backquoted(!in_heredoc) {
+                Some(part) => self.quoted_parts.push(part),
+                None => self.push_char(c),
+            },
+            _ => self.push_char(c),
+        }
+    }
+
+    /// Reads the parameter expansion or command substitution starting at the `$` under the
+    /// cursor, leaving the cursor on its last character. A `$` that doesn't start one is just
+    /// text, and so is an unterminated `${` or `$(`, which leaves the input incomplete.
+    fn read_expansion(&mut self) -> Option<WordPart> {
+        let start = self.position + 1;
+        let first = *self.input.get(start)?;
+        let end = match first {
+            '(' => {
+                if let Some(end) = self.find_arithmetic(start) {
+                    let expression: String = self.input[start + 2..end - 1].iter().collect();
+                    self.position = end;
+                    return Some(WordPart::Arithmetic(Self::lex_operand(&expression)));
+                }
+                let Some(end) = self.find_substitution_end(start + 1) else {
+                    self.incomplete = true;
+                    return None;
+                };
+                let source: String = self.input[start + 1..end].iter().collect();
+                self.position = end;
+                return Some(WordPart::CommandSubst(source));
+            }
+            '{' => {
+                let Some(end) = self.find_closing(start, '{', '}') else {
+                    self.incomplete = true;
+                    return None;
+                };
+                let inner: String = self.input[start + 1..end].iter().collect();
+                self.position = end;
+                return Some(WordPart::Param(Self::parse_braced_param(&inner)));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => self.input[start..]
+                .iter()
+                .position(|&ch| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                .map_or(self.input.len(), |i| start + i),
+            // positional parameters past $9 need braces, as in ${10}
+            c if c.is_ascii_digit() || is_special_param(c) => start + 1,
+            _ => return None,
+        };
+        let name: String = self.input[start..end].iter().collect();
+        self.position = end - 1;
+        Some(WordPart::Param(Param::new(&name)))
+    }
+
+    /// Reads a `` `command` `` substitution starting at the backquote under the cursor, leaving
+    /// the cursor on the closing one. Backslashes only escape `$`, `` ` `` and `\` inside, plus
+    /// `"` if the backquotes are themselves in double quotes.
+    fn read_backquoted(&mut self, in_double_quotes: bool) -> Option<WordPart> {
+        let mut source = String::new();
+        let mut i = self.position + 1;
+        loop {
+            let Some(&c) = self.input.get(i) else {
+                self.incomplete = true;
+                return None;
+            };
+            match c {
+                '`' => break,
+                '\\' => match self.input.get(i + 1) {
+                    Some(&next @ ('$' | '`' | '\\')) => {
+                        source.push(next);
+                        i += 1;
+                    }
+                    Some('"') if in_double_quotes => {
+                        source.push('"');
+                        i += 1;
+                    }
+                    _ => source.push('\\'),
+                },
+                c => source.push(c),
+            }
+            i += 1;
+        }
+        self.position = i;
+        Some(WordPart::CommandSubst(source))
+    }
+
+    /// Finds the end of `((expression))` starting at `start`, as the offset of the final `)`.
+    /// `None` if the parentheses don't pair up that way, as in `$( (cd /tmp); ls )`.
+    fn find_arithmetic(&self, start: usize) -> Option<usize> {
+        if self.input.get(start + 1) != Some(&'(') {
+            return None;
+        }
+        let end = self.find_closing(start, '(', ')')?;
+        let inner_end = self.find_closing(start + 1, '(', ')')?;
+        (inner_end + 1 == end).then_some(end)
+    }
+
+    /// Finds the `)` ending a command substitution whose source starts at `start`. The source is
+    /// lexed to find it, so a `)` that's quoted or ends a case pattern doesn't count.
+    fn find_substitution_end(&self, start: usize) -> Option<usize> {
+        let mut lexer = Lexer::new("");
+        lexer.input = self.input.clone();
+        lexer.position = start;
+        lexer.in_substitution = true;
+        lexer.lex();
+        lexer.substitution_end
+    }
+
+    /// Finds the `close` matching the `open` at `start`, skipping over quoted text and nested
+    /// pairs.
+    fn find_closing(&self, start: usize, open: char, close: char) -> Option<usize> {
+        let mut depth = 0;
+        let mut quote = None;
+        let mut i = start;
+        loop {
+            match (quote, *self.input.get(i)?) {
+                (Some('\''), '\'') => quote = None,
+                (Some('\''), _) => {}
+                (_, '\\') => i += 1,
+                (Some('"'), '"') => quote = None,
+                (Some(_), _) => {}
+                (None, c @ ('\'' | '"')) => quote = Some(c),
+                (None, c) if c == open => depth += 1,
+                (None, c) if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    /// Splits what's between `${` and `}` into the parameter and the operation on it. Anything
+    /// that doesn't parse is kept whole as the name, which expansion then rejects as a bad
+    /// substitution.
+    fn parse_braced_param(inner: &str) -> Param {
+        // ${#NAME} is the length of NAME, but ${#} alone is $#
+        if let Some(name) = inner.strip_prefix('#') {
+            if param_name_len(name) == name.len() && !name.is_empty() {
+                return Param {
+                    name: name.to_string(),
+                    op: ParamOp::Length,
+                };
+            }
+        }
+
+        let (name, rest) = inner.split_at(param_name_len(inner));
+        let (colon, rest) = match rest.strip_prefix(':') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let word = |text: &str| Self::lex_operand(text);
+        let mut chars = rest.chars();
+        let op = match (colon, chars.next()) {
+            (false, None) => ParamOp::Value,
+            (_, Some('-')) => ParamOp::Default {
+                colon,
+                word: word(chars.as_str()),
+            },
+            (_, Some('=')) => ParamOp::Assign {
+                colon,
+                word: word(chars.as_str()),
+            },
+            (_, Some('?')) => ParamOp::Error {
+                colon,
+                word: word(chars.as_str()),
+            },
+            (_, Some('+')) => ParamOp::Alternative {
+                colon,
+                word: word(chars.as_str()),
+            },
+            (true, _) => {
+                let (offset, length) = split_unquoted(rest, ':');
+                ParamOp::Substring {
+                    offset: word(offset),
+                    length: length.map(word),
+                }
+            }
+            (false, Some('#')) => match rest.strip_prefix("##") {
+                Some(pattern) => ParamOp::RemovePrefix {
+                    longest: true,
+                    pattern: word(pattern),
+                },
+                None => ParamOp::RemovePrefix {
+                    longest: false,
+                    pattern: word(chars.as_str()),
+                },
+            },
+            (false, Some('%')) => match rest.strip_prefix("%%") {
+                Some(pattern) => ParamOp::RemoveSuffix {
+                    longest: true,
+                    pattern: word(pattern),
+                },
+                None => ParamOp::RemoveSuffix {
+                    longest: false,
+                    pattern: word(chars.as_str()),
+                },
+            },
+            (false, Some('/')) => {
+                let rest = chars.as_str();
+                let (anchor, rest) = match rest.chars().next() {
+                    Some('/') => (ReplaceAnchor::All, &rest[1..]),
+                    Some('#') => (ReplaceAnchor::Start, &rest[1..]),
+                    Some('%') => (ReplaceAnchor::End, &rest[1..]),
+                    _ => (ReplaceAnchor::First, rest),
+                };
+                let (pattern, replacement) = split_unquoted(rest, '/');
+                ParamOp::Replace {
+                    anchor,
+                    pattern: word(pattern),
+                    replacement: word(replacement.unwrap_or_default()),
+                }
             }
-            _ => self.current_token.push(c),
+            (false, Some(_)) => return Param::new(inner),
+        };
+        Param {
+            name: name.to_string(),
+            op,
+        }
+    }
+
+    /// Lexes the word inside `${NAME-word}`, where quotes and expansions work as usual but blanks
+    /// and operators are just text.
+    fn lex_operand(text: &str) -> Word {
+        let mut lexer = Lexer::new(text);
+        lexer.in_operand = true;
+        while lexer.position < lexer.input.len() {
+            let c = lexer.input[lexer.position];
+            lexer.handle_char(c);
+            lexer.advance();
+        }
+        lexer.close_quotes();
+        Word {
+            parts: lexer.current_token,
+        }
+    }
+
+    /// Adds a character of plain text to the word being built, or to the quotes open inside it.
+    fn push_char(&mut self, c: char) {
+        let parts = match self.current_state {
+            LexerState::InDoubleQuote | LexerState::InHereDoc => &mut self.quoted_parts,
+            _ => &mut self.current_token,
+        };
+        match parts.last_mut() {
+            Some(WordPart::Literal(text)) => text.push(c),
+            _ => parts.push(WordPart::Literal(c.to_string())),
+        }
+    }
+
+    /// The word being built, if it's plain text so far.
+    fn current_literal(&self) -> Option<&str> {
+        match self.current_token.as_slice() {
+            [WordPart::Literal(text)] if !self.current_token_quoted => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Whether the word being built ends in an unquoted `?`, `*`, `+`, `@` or `!`, which makes a
+    /// `(` right after it open an extglob group.
+    fn ends_with_extglob_prefix(&self) -> bool {
+        match self.current_token.last() {
+            Some(WordPart::Literal(text)) => text.ends_with(['?', '*', '+', '@', '!']),
+            _ => false,
         }
     }
 
@@ -159,21 +648,440 @@ impl Lexer {
     }
 
     fn emit_token(&mut self) {
-        if self.current_token.is_empty() {
+        if self.current_token.is_empty() && !self.current_token_quoted {
             return;
         }
-        match self.current_state {
-            LexerState::Normal => {
-                if let Ok(operator) = self.current_token.parse::<Operator>() {
-                    self.tokens.push(Token::Operator(operator))
-                } else {
-                    self.tokens.push(Token::Word(self.current_token.clone()))
+        // quoted text is never an operator, so `echo '>'` prints >
+        let operator = self
+            .current_literal()
+            .and_then(|text| text.parse::<Operator>().ok());
+        let word = Word {
+            parts: std::mem::take(&mut self.current_token),
+        };
+        match operator {
+            Some(operator) => {
+                self.heredoc_delimiter = match operator {
+                    Operator::HereDoc(_) => Some(false),
+                    Operator::HereDocStripTabs(_) => Some(true),
+                    _ => None,
+                };
+                // the operator's last character is the current one
+                self.push_token(Token::Operator(operator), self.position + 1);
+            }
+            None => {
+                if let Some(strip_tabs) = self.heredoc_delimiter.take() {
+                    self.pending_heredocs.push(PendingHereDoc {
+                        token_index: self.tokens.len(),
+                        delimiter: word.unquoted_text(),
+                        quoted: word.is_quoted(),
+                        strip_tabs,
+                    });
+                }
+                // a word ends where the character that ended it is
+                match self.reserved_word(&word) {
+                    Some(reserved) => self.push_token(Token::Reserved(reserved), self.position),
+                    None => self.push_token(Token::Word(word), self.position),
+                }
+            }
+        }
+        self.current_token_quoted = false;
+        self.token_start = self.position;
+    }
+
+    /// The reserved word a word is, if it's one and where a command starts, keeping count of the
+    /// compound commands it opens and closes.
+    fn reserved_word(&mut self, word: &Word) -> Option<Reserved> {
+        let literal = word.as_literal()?;
+        match self.cases.last() {
+            // the word after `case`, and patterns other than a leading `esac`, are plain words
+            Some(CaseState::Word | CaseState::In) => return None,
+            Some(CaseState::Pattern { start }) if !start || literal != "esac" => return None,
+            Some(CaseState::Pattern { .. }) => {}
+            Some(CaseState::Body) | None if !self.command_start => return None,
+            Some(CaseState::Body) | None => {}
+        }
+        let reserved = literal.parse::<Reserved>().ok()?;
+        match reserved {
+            Reserved::If | Reserved::While | Reserved::Until | Reserved::For | Reserved::Case => {
+                self.open_compounds += 1
+            }
+            Reserved::Fi | Reserved::Done | Reserved::Esac => {
+                self.open_compounds = self.open_compounds.saturating_sub(1)
+            }
+            _ => {}
+        }
+        Some(reserved)
+    }
+
+    /// Adds a token that started at `token_start` and ends just before `end`.
+    fn push_token(&mut self, token: Token, end: usize) {
+        self.command_start = match &token {
+            Token::Operator(op) => op.is_control(),
+            Token::Reserved(reserved) => reserved.starts_list(),
+            Token::Word(_) | Token::Arithmetic(_) => false,
+        };
+        self.track_case(&token);
+        let span = Span {
+            start: self.byte_offset(self.token_start),
+            end: self.byte_offset(end),
+        };
+        self.tokens.push(SpannedToken { token, span });
+    }
+
+    /// Moves the innermost `case` along to what comes after `token`, and counts the parentheses
+    /// that aren't part of one.
+    fn track_case(&mut self, token: &Token) {
+        let state = self.cases.last().copied();
+        let next = match (state, token) {
+            (_, Token::Reserved(Reserved::Case)) => {
+                self.cases.push(CaseState::Word);
+                return;
+            }
+            (
+                Some(CaseState::Pattern { .. } | CaseState::Body),
+                Token::Reserved(Reserved::Esac),
+            ) => {
+                self.cases.pop();
+                return;
+            }
+            (Some(CaseState::Word), _) => CaseState::In,
+            (Some(CaseState::In), Token::Operator(Operator::Newline)) => CaseState::In,
+            (Some(CaseState::In), _) => CaseState::Pattern { start: true },
+            (Some(CaseState::Pattern { .. }), Token::Operator(Operator::RightParen)) => {
+                self.command_start = true;
+                CaseState::Body
+            }
+            (Some(CaseState::Pattern { start }), Token::Operator(Operator::Newline)) => {
+                CaseState::Pattern { start }
+            }
+            (Some(CaseState::Pattern { .. }), _) => CaseState::Pattern { start: false },
+            (Some(CaseState::Body), Token::Operator(Operator::DoubleSemi)) => {
+                CaseState::Pattern { start: true }
+            }
+            (_, Token::Operator(Operator::LeftParen)) => {
+                self.paren_depth += 1;
+                return;
+            }
+            (_, Token::Operator(Operator::RightParen)) => {
+                self.paren_depth = self.paren_depth.saturating_sub(1);
+                return;
+            }
+            _ => return,
+        };
+        if let Some(last) = self.cases.last_mut() {
+            *last = next;
+        }
+    }
+
+    /// The byte offset in the source of the character at `index` in `input`.
+    fn byte_offset(&self, index: usize) -> usize {
+        let index = index.min(self.input.len());
+        self.input[..index].iter().map(|c| c.len_utf8()).sum()
+    }
+
+    /// Reads the bodies of here-documents started on the line that just ended, replacing each
+    /// delimiter token with the body.
+    fn read_heredoc_bodies(&mut self) {
+        while !self.pending_heredocs.is_empty() {
+            let heredoc = &self.pending_heredocs[0];
+            let mut body = String::new();
+            let mut terminated = false;
+
+            while self.position + 1 < self.input.len() {
+                let start = self.position + 1;
+                let end = self.input[start..]
+                    .iter()
+                    .position(|&ch| ch == '\n')
+                    .map_or(self.input.len(), |i| start + i);
+                let line: String = self.input[start..end].iter().collect();
+                // leave position on the newline ending the line, which the caller advances past
+                self.position = end.min(self.input.len() - 1);
+
+                let line = match heredoc.strip_tabs {
+                    true => line.trim_start_matches('\t'),
+                    false => &line,
+                };
+                if line == heredoc.delimiter {
+                    terminated = true;
+                    break;
                 }
+                body.push_str(line);
+                body.push('\n');
             }
-            LexerState::InSingleQuote | LexerState::InDoubleQuote => {
-                self.tokens.push(Token::Word(self.current_token.clone()));
+
+            if !terminated {
+                // the delimiter hasn't been typed yet
+                return;
             }
+            let heredoc = self.pending_heredocs.remove(0);
+            let body = match heredoc.quoted {
+                true => Word {
+                    parts: vec![WordPart::SingleQuoted(body)],
+                },
+                false => Self::lex_heredoc_body(&body),
+            };
+            self.tokens[heredoc.token_index].token = Token::Word(body);
         }
-        self.current_token.clear();
+    }
+}
+
+fn is_special_param(c: char) -> bool {
+    matches!(c, '?' | '$' | '!' | '#' | '@' | '*')
+}
+
+/// The length of the parameter name at the start of `text`: a variable name, a positional
+/// parameter number or a special parameter.
+fn param_name_len(text: &str) -> usize {
+    match text.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => text
+            .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+            .unwrap_or(text.len()),
+        Some(c) if c.is_ascii_digit() => text
+            .find(|ch: char| !ch.is_ascii_digit())
+            .unwrap_or(text.len()),
+        Some(c) if is_special_param(c) => 1,
+        _ => 0,
+    }
+}
+
+/// Splits `text` at the first `sep` that isn't quoted or escaped.
+fn split_unquoted(text: &str, sep: char) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, c) if c == sep => return (&text[..i], Some(&text[i + 1..])),
+            _ => {}
+        }
+    }
+    (text, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn needs_more_input(source: &str) -> bool {
+        let mut lexer = Lexer::new(source);
+        lexer.lex();
+        lexer.needs_more_input()
+    }
+
+    #[test]
+    fn waits_for_here_document_delimiters() {
+        assert!(needs_more_input("cat <<EOF\nhi\n"));
+        assert!(needs_more_input("cat <<EOF\nEOF x\n"));
+        assert!(!needs_more_input("cat <<EOF\nhi\nEOF\n"));
+        assert!(!needs_more_input("cat <<-EOF\n\tEOF"));
+        assert!(!needs_more_input("cat <<< EOF"));
+    }
+
+    fn words(source: &str) -> Vec<Word> {
+        Lexer::new(source)
+            .lex()
+            .into_iter()
+            .filter_map(|spanned| match spanned.token {
+                Token::Word(word) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn param(name: &str) -> WordPart {
+        WordPart::Param(Param::new(name))
+    }
+
+    fn literal(text: &str) -> WordPart {
+        WordPart::Literal(text.to_string())
+    }
+
+    #[test]
+    fn keeps_track_of_quoting_around_parameters() {
+        let [word] = &words(r#"$HOME"x$1"'$y'\$z"#)[..] else {
+            panic!("expected one word");
+        };
+        assert_eq!(
+            word.parts,
+            [
+                param("HOME"),
+                WordPart::DoubleQuoted(vec![literal("x"), param("1")]),
+                WordPart::SingleQuoted("$y".to_string()),
+                WordPart::Escaped('$'),
+                literal("z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_parameter_names() {
+        let parts: Vec<Vec<WordPart>> = words("${a}b $1x $?$$ $a.b")
+            .into_iter()
+            .map(|word| word.parts)
+            .collect();
+        assert_eq!(
+            parts,
+            [
+                vec![param("a"), literal("b")],
+                vec![param("1"), literal("x")],
+                vec![param("?"), param("$")],
+                vec![param("a"), literal(".b")],
+            ]
+        );
+    }
+
+    #[test]
+    fn a_dollar_without_a_name_is_text() {
+        assert_eq!(
+            words("a$ $ ${x")[..2],
+            [Word::literal("a$"), Word::literal("$")]
+        );
+        assert_eq!(words("${x").len(), 1);
+    }
+
+    fn op(source: &str) -> ParamOp {
+        match &words(source)[0].parts[..] {
+            [WordPart::Param(param)] => param.op.clone(),
+            parts => panic!("expected a parameter: {:?}", parts),
+        }
+    }
+
+    #[test]
+    fn parses_parameter_operators() {
+        assert_eq!(op("${#x}"), ParamOp::Length);
+        assert_eq!(
+            op("${x:-a b}"),
+            ParamOp::Default {
+                colon: true,
+                word: Word::literal("a b")
+            }
+        );
+        assert_eq!(
+            op("${x##*/}"),
+            ParamOp::RemovePrefix {
+                longest: true,
+                pattern: Word::literal("*/")
+            }
+        );
+        assert_eq!(
+            op("${x/#a/b}"),
+            ParamOp::Replace {
+                anchor: ReplaceAnchor::Start,
+                pattern: Word::literal("a"),
+                replacement: Word::literal("b")
+            }
+        );
+        assert_eq!(
+            op("${x:1:2}"),
+            ParamOp::Substring {
+                offset: Word::literal("1"),
+                length: Some(Word::literal("2"))
+            }
+        );
+    }
+
+    #[test]
+    fn records_where_each_token_was() {
+        let spans: Vec<(usize, usize)> = Lexer::new("echo  'a b'|wc\n")
+            .lex()
+            .iter()
+            .map(|spanned| (spanned.span.start, spanned.span.end))
+            .collect();
+        assert_eq!(spans, [(0, 4), (6, 11), (11, 12), (12, 14), (14, 15)]);
+    }
+
+    #[test]
+    fn waits_for_closing_quotes_and_substitutions() {
+        assert!(needs_more_input("echo 'abc"));
+        assert!(needs_more_input("echo \"abc\ndef"));
+        assert!(needs_more_input("echo $(ls"));
+        assert!(needs_more_input("echo abc\\"));
+        assert!(!needs_more_input("echo 'a\nb'"));
+        assert!(!needs_more_input("echo \"it's\""));
+    }
+
+    #[test]
+    fn waits_for_the_command_after_a_pipe_or_and_or() {
+        assert!(needs_more_input("ls |"));
+        assert!(needs_more_input("true &&"));
+        assert!(needs_more_input("false ||"));
+        assert!(!needs_more_input("ls | wc"));
+        assert!(!needs_more_input("sleep 1 &"));
+        assert!(!needs_more_input("echo '|'"));
+    }
+
+    #[test]
+    fn keeps_waiting_through_continued_lines() {
+        assert!(needs_more_input("ls |\n"));
+        assert!(needs_more_input("true &&\n\n"));
+        assert!(needs_more_input("if true\nthen\n  echo a\n"));
+        assert!(!needs_more_input("if true\nthen\n  echo a\nfi"));
+        assert!(needs_more_input("while false\ndo\n"));
+        assert!(!needs_more_input("while false\ndo\n  echo\ndone\n"));
+        assert!(needs_more_input("case $x in\n  a)\n    echo a\n    ;;\n"));
+        assert!(!needs_more_input(
+            "case $x in\n  a)\n    echo a\n    ;;\nesac"
+        ));
+        assert!(needs_more_input("echo 'one\ntwo\n"));
+        assert!(!needs_more_input("echo 'one\ntwo'\n"));
+        assert!(needs_more_input("cat <<EOF |\nbody\nEOF\n"));
+        assert!(!needs_more_input("cat <<EOF |\nbody\nEOF\nwc -l"));
+    }
+
+    #[test]
+    fn waits_for_compound_commands_to_close() {
+        assert!(needs_more_input("if true; then"));
+        assert!(needs_more_input("if true; then echo; fi; while x"));
+        assert!(needs_more_input("while true\ndo echo"));
+        assert!(needs_more_input("until false; do"));
+        assert!(needs_more_input("for x in a b"));
+        assert!(needs_more_input("case x in\n a) echo;;"));
+        assert!(needs_more_input("if a; then if b; then c; fi"));
+        assert!(!needs_more_input("if a; then b; fi"));
+        assert!(!needs_more_input("for x in a; do echo $x; done"));
+        assert!(!needs_more_input("case x in a) echo;; esac"));
+        // only where a command starts
+        assert!(!needs_more_input("echo if while"));
+    }
+
+    #[test]
+    fn case_patterns_dont_open_or_close_compounds() {
+        assert!(!needs_more_input("case x in esac"));
+        assert!(!needs_more_input("case x in\nesac"));
+        assert!(!needs_more_input("case x in a) ;; esac"));
+        assert!(!needs_more_input("case x in (if|esac) echo;; esac"));
+        assert!(!needs_more_input(
+            "case x in a) case y in b) ;; esac;; esac"
+        ));
+        assert!(needs_more_input("case x in a) ;; (esac)"));
+        assert!(needs_more_input("case x in a) if true"));
+        assert!(needs_more_input("case x in a) case y in b) ;; esac;;"));
+    }
+
+    #[test]
+    fn command_substitution_ends_at_its_own_closing_paren() {
+        let substitution = |source: &str| match &words(source)[..] {
+            [_, word] => match &word.parts[..] {
+                [WordPart::CommandSubst(source)] => source.clone(),
+                parts => panic!("expected a substitution: {:?}", parts),
+            },
+            words => panic!("expected two words: {:?}", words),
+        };
+        assert_eq!(
+            substitution("echo $(case x in a) echo;; esac)"),
+            "case x in a) echo;; esac"
+        );
+        assert_eq!(
+            substitution("echo $(case x in (a) echo ')';; esac)"),
+            "case x in (a) echo ')';; esac"
+        );
+        assert_eq!(substitution("echo $( (echo) )"), " (echo) ");
+        assert!(needs_more_input("echo $(case x in a) echo"));
     }
 }
@@ -3,20 +3,29 @@ extern crate core;
 use rustyline::error::ReadlineError;
 use rustyline::history::{DefaultHistory, History};
 use rustyline::{CompletionType, Editor};
-use std::io::{Read, Write};
 
+mod arithmetic;
+mod brace;
 mod commands;
 mod completer;
 mod enums;
+mod expand;
+mod glob;
+mod jobs;
 mod lexer;
+mod options;
 mod parser;
+mod pattern;
+mod shell;
+mod tilde;
 pub mod util;
+mod variables;
+mod word;
 
-use crate::commands::*;
 use crate::completer::MyHelper;
-use crate::enums::Command;
 use crate::lexer::Lexer;
-use crate::parser::{ASTNode, Parser, Redirection};
+use crate::parser::{ParseError, Parser};
+use crate::shell::Shell;
 
 fn main() -> rustyline::Result<()> {
     let helper = MyHelper {};
@@ -34,42 +43,77 @@ fn main() -> rustyline::Result<()> {
 
     let _ = rl.load_history(&history_file);
 
-    let mut last_saved_history_idx = rl.history().len();
+    let last_saved_history_idx = rl.history().len();
+    let mut args = std::env::args();
+    let name = args.next().unwrap_or_default();
+    let mut shell = Shell::new(rl, last_saved_history_idx, name, args.collect());
 
-    loop {
-        let readline = rl.readline("$ ");
+    'repl: loop {
+        shell.report_jobs();
+        let readline = shell.editor.readline("$ ");
         match readline {
             Ok(line) => {
                 // println!("DEBUG: {:?}", line);
-                let line = line.trim_start();
+                let mut line = line.trim_start().to_string();
                 if line.is_empty() {
                     continue;
                 }
 
-                rl.add_history_entry(line).expect("TODO: panic message");
+                let tokens = loop {
+                    let mut lexer = Lexer::new(&line);
+                    let tokens = lexer.lex();
+                    if !lexer.needs_more_input() {
+                        break tokens;
+                    }
+                    // keep reading lines for open quotes, trailing pipes, here-documents and
+                    // unfinished compound commands
+                    let prompt = shell.vars.get("PS2").unwrap_or("> ");
+                    match shell.editor.readline(prompt) {
+                        Ok(more) => {
+                            line.push('\n');
+                            line.push_str(&more);
+                        }
+                        Err(ReadlineError::Interrupted) => {
+                            // Ctrl-C throws away the whole unfinished command
+                            shell.last_status = 130;
+                            continue 'repl;
+                        }
+                        Err(_) => {
+                            let e = ParseError::UnexpectedEof {
+                                position: line.len(),
+                            };
+                            eprintln!("{}", e);
+                            shell.last_status = 2;
+                            continue 'repl;
+                        }
+                    }
+                };
+
+                // the command still runs if it can't go into the history
+                if let Err(e) = shell.editor.add_history_entry(&line) {
+                    eprintln!("history: {}", e);
+                }
 
-                let mut lexer = Lexer::new(line);
-                let tokens = lexer.lex();
                 // println!("{:#?}", tokens);
                 let mut parser = Parser::new(tokens);
-                let node = parser.parse();
-
-                match node {
-                    ASTNode::Command {
-                        name,
-                        args,
-                        redirections,
-                    } => run_command(
-                        name,
-                        &args,
-                        redirections,
-                        &mut rl,
-                        &mut last_saved_history_idx,
-                    ),
-                    ASTNode::Pipeline(pipeline) => run_pipeline(pipeline),
-                }
+                let node = match parser.parse() {
+                    Ok(node) => node,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        eprintln!("{}", util::point_at(&line, e.position()));
+                        shell.last_status = 2;
+                        continue;
+                    }
+                };
+
+                shell.source = line;
+                let _ = shell.run_node(node);
             }
-            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
+            Err(ReadlineError::Interrupted) => {
+                // Ctrl-C at the prompt only discards the line being typed
+                shell.last_status = 130;
+            }
+            Err(ReadlineError::Eof) => {
                 break;
             }
             Err(err) => {
@@ -79,54 +123,6 @@ fn main() -> rustyline::Result<()> {
         }
     }
     // rl.append_history(&history_file)?;
-    let _ = rl.save_history(&history_file);
-    Ok(())
-}
-
-fn run_pipeline(_pipeline: Vec<ASTNode>) {
-    todo!("Not implemented")
-}
-
-fn run_command(
-    name: String,
-    args: &[String],
-    redirections: Vec<Redirection>,
-    editor: &mut Editor<MyHelper, DefaultHistory>,
-    last_saved_history_idx: &mut usize,
-) {
-    let (mut input, mut output, mut errput) = util::check_streams(redirections);
-    run_command_stream(
-        name,
-        args,
-        &mut input,
-        &mut output,
-        &mut errput,
-        editor,
-        last_saved_history_idx,
-    );
-}
-
-fn run_command_stream(
-    name: String,
-    args: &[String],
-    _input_stream: &mut dyn Read,
-    iostream: &mut dyn Write,
-    err_stream: &mut dyn Write,
-    editor: &mut Editor<MyHelper, DefaultHistory>,
-    last_saved_history_idx: &mut usize,
-) {
-    if let Ok(command) = name.parse::<Command>() {
-        match command {
-            Command::Exit => exit_cmd(&args, editor, last_saved_history_idx),
-            Command::Echo => echo_cmd(&args, iostream),
-            Command::Type => type_cmd(&args, iostream, err_stream),
-            Command::External(path) => external_cmd(path, &args, iostream, err_stream),
-            Command::Pwd => pwd_cmd(iostream),
-            Command::Cd => cd_cmd(&args, err_stream),
-            Command::History => history_cmd(args, iostream, editor, last_saved_history_idx),
-            Command::Invalid => invalid_cmd(&name, err_stream),
-        }
-    } else {
-        panic!("Error parsing command")
-    }
+    let _ = shell.editor.save_history(&history_file);
+    std::process::exit(shell.last_status);
 }
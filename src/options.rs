@@ -0,0 +1,71 @@
+use crate::pattern::MatchOptions;
+
+/// Options toggled with `shopt`, all off by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShellOptions {
+    /// Globs match names starting with `.` without the pattern having to.
+    pub dotglob: bool,
+    pub extglob: bool,
+    /// A glob matching nothing is an error.
+    pub failglob: bool,
+    /// `**` matches any number of directories.
+    pub globstar: bool,
+    pub nocaseglob: bool,
+    /// A glob matching nothing disappears instead of being left as it is.
+    pub nullglob: bool,
+}
+
+impl ShellOptions {
+    pub const NAMES: [&'static str; 6] = [
+        "dotglob",
+        "extglob",
+        "failglob",
+        "globstar",
+        "nocaseglob",
+        "nullglob",
+    ];
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let value = match name {
+            "dotglob" => self.dotglob,
+            "extglob" => self.extglob,
+            "failglob" => self.failglob,
+            "globstar" => self.globstar,
+            "nocaseglob" => self.nocaseglob,
+            "nullglob" => self.nullglob,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Turns an option on or off, returning `None` if there's no such option.
+    pub fn set(&mut self, name: &str, on: bool) -> Option<()> {
+        let option = match name {
+            "dotglob" => &mut self.dotglob,
+            "extglob" => &mut self.extglob,
+            "failglob" => &mut self.failglob,
+            "globstar" => &mut self.globstar,
+            "nocaseglob" => &mut self.nocaseglob,
+            "nullglob" => &mut self.nullglob,
+            _ => return None,
+        };
+        *option = on;
+        Some(())
+    }
+
+    /// How pathname expansion matches names.
+    pub fn glob_matching(&self) -> MatchOptions {
+        MatchOptions {
+            extglob: self.extglob,
+            ignore_case: self.nocaseglob,
+        }
+    }
+
+    /// How `${VAR#pattern}` and the like match, which ignores `nocaseglob`.
+    pub fn pattern_matching(&self) -> MatchOptions {
+        MatchOptions {
+            extglob: self.extglob,
+            ignore_case: false,
+        }
+    }
+}
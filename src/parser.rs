@@ -1,37 +1,303 @@
 use crate::enums::Operator;
+use crate::enums::Reserved;
 use crate::enums::Token;
+use crate::enums::{Span, SpannedToken};
+use crate::variables::is_valid_name;
+use crate::word::{Word, WordPart};
+use std::fmt;
+use std::os::fd::RawFd;
+use thiserror::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ASTNode {
     Command {
-        name: String,
-        args: Vec<String>,
+        assignments: Vec<Assignment>, // NAME=value before the command name
+        name: Option<Word>,           // None if the command only assigns or redirects
+        args: Vec<Word>,
         redirections: Vec<Redirection>,
+        span: Span, // where the command was in the source
     },
     Pipeline(Vec<ASTNode>),
+    And(Box<ASTNode>, Box<ASTNode>), // runs the right side only if the left succeeded
+    Or(Box<ASTNode>, Box<ASTNode>),  // runs the right side only if the left failed
+    Sequence(Vec<ASTNode>),          // commands separated by ; or newlines
+    Background(Box<ASTNode>),        // an and-or list ended by &
+    // ((expression)), which succeeds if it's non-zero
+    Arithmetic {
+        expression: Word,
+        span: Span,
+    },
+    // if, while, until, for or case, with the redirections after its closing word
+    Compound {
+        command: CompoundCommand,
+        redirections: Vec<Redirection>,
+        span: Span,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum CompoundCommand {
+    // the conditions of `if` and each `elif` with the commands they run, then the `else` part
+    If {
+        branches: Vec<(ASTNode, ASTNode)>,
+        otherwise: Option<Box<ASTNode>>,
+    },
+    // `until` loops while the condition fails rather than while it succeeds
+    While {
+        until: bool,
+        condition: Box<ASTNode>,
+        body: Box<ASTNode>,
+    },
+    // without `in`, the loop goes over the positional parameters
+    For {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: Box<ASTNode>,
+    },
+    // for ((init; condition; update)), where an empty condition is always true
+    ArithmeticFor {
+        init: Option<Word>,
+        condition: Option<Word>,
+        update: Option<Word>,
+        body: Box<ASTNode>,
+    },
+    Case {
+        word: Word,
+        items: Vec<CaseItem>,
+    },
+}
+
+/// The patterns of a `case` item and the commands run when one of them matches.
+#[derive(Debug, Clone)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: ASTNode,
+}
+
+impl ASTNode {
+    /// Where the node was in the source, from the start of its first command to the end of its
+    /// last.
+    pub fn span(&self) -> Span {
+        let (first, last) = match self {
+            ASTNode::Command { span, .. }
+            | ASTNode::Arithmetic { span, .. }
+            | ASTNode::Compound { span, .. } => return *span,
+            ASTNode::Background(node) => return node.span(),
+            ASTNode::And(left, right) | ASTNode::Or(left, right) => (left.span(), right.span()),
+            ASTNode::Pipeline(nodes) | ASTNode::Sequence(nodes) => {
+                match (nodes.first(), nodes.last()) {
+                    (Some(first), Some(last)) => (first.span(), last.span()),
+                    _ => return Span::default(),
+                }
+            }
+        };
+        Span {
+            start: first.start,
+            end: last.end,
+        }
+    }
+}
+
+impl fmt::Display for ASTNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ASTNode::Command {
+                assignments,
+                name,
+                args,
+                redirections,
+                ..
+            } => {
+                let assignments = assignments.iter().map(|a| a.to_string());
+                let words = name.iter().chain(args).map(|word| word.to_string());
+                let redirections = redirections.iter().map(|r| r.to_string());
+                let parts: Vec<String> = assignments.chain(words).chain(redirections).collect();
+                write!(f, "{}", parts.join(" "))
+            }
+            ASTNode::Pipeline(pipeline) => {
+                let stages: Vec<String> = pipeline.iter().map(|node| node.to_string()).collect();
+                write!(f, "{}", stages.join(" | "))
+            }
+            ASTNode::And(left, right) => write!(f, "{} && {}", left, right),
+            ASTNode::Or(left, right) => write!(f, "{} || {}", left, right),
+            ASTNode::Sequence(nodes) => {
+                let nodes: Vec<String> = nodes.iter().map(|node| node.to_string()).collect();
+                write!(f, "{}", nodes.join("; "))
+            }
+            ASTNode::Background(node) => write!(f, "{} &", node),
+            ASTNode::Arithmetic { expression, .. } => write!(f, "(({}))", expression),
+            ASTNode::Compound {
+                command,
+                redirections,
+                ..
+            } => {
+                write!(f, "{}", command)?;
+                for redirection in redirections {
+                    write!(f, " {}", redirection)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for CompoundCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = |word: &Option<Word>| word.as_ref().map(Word::to_string).unwrap_or_default();
+        match self {
+            CompoundCommand::If {
+                branches,
+                otherwise,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = match i {
+                        0 => "if",
+                        _ => "elif",
+                    };
+                    write!(f, "{} {}; then {}; ", keyword, condition, body)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, "else {}; ", otherwise)?;
+                }
+                write!(f, "fi")
+            }
+            CompoundCommand::While {
+                until,
+                condition,
+                body,
+            } => {
+                let keyword = match until {
+                    true => "until",
+                    false => "while",
+                };
+                write!(f, "{} {}; do {}; done", keyword, condition, body)
+            }
+            CompoundCommand::For { name, words, body } => {
+                write!(f, "for {}", name)?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    for word in words {
+                        write!(f, " {}", word)?;
+                    }
+                }
+                write!(f, "; do {}; done", body)
+            }
+            CompoundCommand::ArithmeticFor {
+                init,
+                condition,
+                update,
+                body,
+            } => write!(
+                f,
+                "for (({}; {}; {})); do {}; done",
+                optional(init),
+                optional(condition),
+                optional(update),
+                body
+            ),
+            CompoundCommand::Case { word, items } => {
+                write!(f, "case {} in", word)?;
+                for item in items {
+                    let patterns: Vec<String> =
+                        item.patterns.iter().map(|word| word.to_string()).collect();
+                    write!(f, " {}) {};;", patterns.join(" | "), item.body)?;
+                }
+                write!(f, " esac")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
 }
 
-#[derive(Debug)]
-pub struct Redirection {
-    pub fd: u8,
+/// A redirection as parsed, with words in its target, or as run, once those have been expanded.
+#[derive(Debug, Clone)]
+pub struct Redirection<W = Word> {
+    pub fd: RawFd,
     pub direction: RedirectionType,
-    pub target: String,
+    pub target: RedirectionTarget<W>,
 }
 
-#[derive(Debug)]
+impl<W: fmt::Display> fmt::Display for Redirection<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match (&self.direction, &self.target) {
+            (_, RedirectionTarget::HereDoc(_)) => "<<",
+            (RedirectionType::Input, RedirectionTarget::File(_)) => "<",
+            (RedirectionType::Input, _) => "<&",
+            (RedirectionType::Output, RedirectionTarget::File(_)) => ">",
+            (RedirectionType::Output, _) => ">&",
+            (RedirectionType::Append, _) => ">>",
+            (RedirectionType::ReadWrite, _) => "<>",
+        };
+        let default_fd = match self.direction {
+            RedirectionType::Input | RedirectionType::ReadWrite => 0,
+            RedirectionType::Output | RedirectionType::Append => 1,
+        };
+        if self.fd != default_fd {
+            write!(f, "{}", self.fd)?;
+        }
+        match &self.target {
+            RedirectionTarget::File(path) => write!(f, "{}{}", op, path),
+            RedirectionTarget::Fd(fd) => write!(f, "{}{}", op, fd),
+            RedirectionTarget::Close => write!(f, "{}-", op),
+            RedirectionTarget::HereDoc(_) => write!(f, "{} here-document", op),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RedirectionTarget<W = Word> {
+    File(W),
+    Fd(RawFd),  // >&2, <&0
+    Close,      // >&-, <&-
+    HereDoc(W), // the text fed to the command by <<, <<- and <<<
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum RedirectionType {
     Input,
     Output,
     Append,
+    ReadWrite,
+}
+
+/// A command line that doesn't parse, along with where the problem is. None of it runs.
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("syntax error near unexpected token `{token}'")]
+    UnexpectedToken { token: String, span: Span },
+    // the line ended in the middle of a command, as in `echo hi |`
+    #[error("syntax error: unexpected end of file")]
+    UnexpectedEof { position: usize },
+}
+
+impl ParseError {
+    /// The byte offset in the source the error points at.
+    pub fn position(&self) -> usize {
+        match self {
+            ParseError::UnexpectedToken { span, .. } => span.start,
+            ParseError::UnexpectedEof { position } => *position,
+        }
+    }
 }
 
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<SpannedToken>,
     position: usize,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(tokens: Vec<SpannedToken>) -> Self {
         Self {
             tokens,
             position: 0,
@@ -39,27 +305,161 @@ impl Parser {
     }
 
     fn peek_token(&self) -> Option<&Token> {
-        self.tokens.get(self.position)
+        self.tokens.get(self.position).map(|spanned| &spanned.token)
     }
 
     fn consume_token(&mut self) -> Option<Token> {
         if self.position < self.tokens.len() {
-            let token = self.tokens[self.position].clone();
+            let token = self.tokens[self.position].token.clone();
             self.position += 1;
             Some(token)
         } else {
             None
         }
     }
-    pub fn parse(&mut self) -> ASTNode {
+
+    /// The source covered by the tokens from `first` up to the current one.
+    fn span_from(&self, first: usize) -> Span {
+        let spans = &self.tokens[first..self.position];
+        match (spans.first(), spans.last()) {
+            (Some(first), Some(last)) => Span {
+                start: first.span.start,
+                end: last.span.end,
+            },
+            _ => Span::default(),
+        }
+    }
+
+    /// The error for the current token turning up where it can't go.
+    fn unexpected(&self) -> ParseError {
+        let Some(spanned) = self.tokens.get(self.position) else {
+            let position = self.tokens.last().map_or(0, |last| last.span.end);
+            return ParseError::UnexpectedEof { position };
+        };
+        let token = match &spanned.token {
+            Token::Word(word) => word.to_string(),
+            Token::Operator(Operator::Newline) => "newline".to_string(),
+            Token::Operator(op) => op.to_string(),
+            Token::Arithmetic(_) => "((".to_string(),
+            Token::Reserved(reserved) => reserved.to_string(),
+        };
+        ParseError::UnexpectedToken {
+            token,
+            span: spanned.span,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some(Token::Operator(Operator::Newline)) = self.peek_token() {
+            self.consume_token();
+        }
+    }
+
+    /// Parses a whole line: and-or lists separated by `;`, `&` or newlines.
+    pub fn parse(&mut self) -> Result<ASTNode, ParseError> {
+        let sequence = self.parse_list(&[])?;
+        // a `;;` or a reserved word like `fi` with nothing for it to close
+        if self.peek_token().is_some() {
+            return Err(self.unexpected());
+        }
+        Ok(Self::sequence(sequence))
+    }
+
+    /// Parses and-or lists separated by `;`, `&` or newlines, up to the end of the input, a `;;`,
+    /// or one of the `terminators` where a command would start.
+    fn parse_list(&mut self, terminators: &[Reserved]) -> Result<Vec<ASTNode>, ParseError> {
+        let mut sequence = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek_token() {
+                None | Some(Token::Operator(Operator::DoubleSemi)) => break,
+                Some(Token::Reserved(reserved)) if terminators.contains(reserved) => break,
+                _ => {}
+            }
+            let node = self.parse_and_or()?;
+
+            match self.peek_token() {
+                Some(Token::Operator(Operator::Semi | Operator::Newline)) => {
+                    self.consume_token();
+                    sequence.push(node);
+                }
+                Some(Token::Operator(Operator::Background)) => {
+                    self.consume_token();
+                    sequence.push(ASTNode::Background(Box::new(node)));
+                }
+                _ => {
+                    sequence.push(node);
+                    break;
+                }
+            }
+        }
+        Ok(sequence)
+    }
+
+    /// Parses the commands inside a compound command, which can't be empty.
+    fn parse_body(&mut self, terminators: &[Reserved]) -> Result<ASTNode, ParseError> {
+        let sequence = self.parse_list(terminators)?;
+        if sequence.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(Self::sequence(sequence))
+    }
+
+    fn sequence(mut sequence: Vec<ASTNode>) -> ASTNode {
+        if sequence.len() == 1 {
+            sequence.pop().unwrap()
+        } else {
+            ASTNode::Sequence(sequence)
+        }
+    }
+
+    /// Consumes the reserved word `expected`, which has to be next.
+    fn expect(&mut self, expected: Reserved) -> Result<(), ParseError> {
+        match self.peek_token() {
+            Some(Token::Reserved(reserved)) if *reserved == expected => {
+                self.consume_token();
+                Ok(())
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Whether the next token is the word `in`, which is only reserved after `for name` and
+    /// `case word`.
+    fn next_is_in(&self) -> bool {
+        matches!(self.peek_token(), Some(Token::Word(word)) if word.as_literal() == Some("in"))
+    }
+
+    /// `&&` and `||` have equal precedence and associate to the left, binding looser than `|`.
+    fn parse_and_or(&mut self) -> Result<ASTNode, ParseError> {
+        let mut node = self.parse_pipeline()?;
+        loop {
+            match self.peek_token() {
+                Some(Token::Operator(Operator::And)) => {
+                    self.consume_token();
+                    self.skip_newlines();
+                    node = ASTNode::And(Box::new(node), Box::new(self.parse_pipeline()?));
+                }
+                Some(Token::Operator(Operator::Or)) => {
+                    self.consume_token();
+                    self.skip_newlines();
+                    node = ASTNode::Or(Box::new(node), Box::new(self.parse_pipeline()?));
+                }
+                _ => return Ok(node),
+            }
+        }
+    }
+
+    fn parse_pipeline(&mut self) -> Result<ASTNode, ParseError> {
         let mut pipeline = Vec::new();
         loop {
-            pipeline.push(self.parse_command());
+            pipeline.push(self.parse_command()?);
 
             match self.peek_token() {
                 Some(Token::Operator(Operator::Pipe)) => {
                     // | is not part of either command
                     self.consume_token();
+                    self.skip_newlines();
                 }
                 _ => break,
             }
@@ -67,57 +467,694 @@ impl Parser {
 
         if pipeline.len() == 1 {
             // return the command directly
-            pipeline.pop().unwrap()
+            Ok(pipeline.pop().unwrap())
         } else {
-            ASTNode::Pipeline(pipeline)
+            Ok(ASTNode::Pipeline(pipeline))
         }
     }
 
-    fn parse_command(&mut self) -> ASTNode {
+    fn parse_command(&mut self) -> Result<ASTNode, ParseError> {
+        let first = self.position;
+        if let Some(Token::Reserved(reserved)) = self.peek_token() {
+            return self.parse_compound(*reserved);
+        }
+        if let Some(Token::Arithmetic(expression)) = self.peek_token() {
+            let expression = expression.clone();
+            self.consume_token();
+            return Ok(ASTNode::Arithmetic {
+                expression,
+                span: self.span_from(first),
+            });
+        }
+
+        let mut assignments = Vec::new();
         let mut name = None;
         let mut args = Vec::new();
         let mut redirs = Vec::new();
 
         while let Some(token) = self.peek_token() {
             match token {
-                Token::Operator(Operator::Pipe) => break,
-                Token::Operator(op) => {
-                    let op = op.clone();
+                Token::Operator(op) if op.is_control() => break,
+                Token::Operator(_) => redirs.extend(self.parse_redirection(first)?),
+                Token::Arithmetic(expression) => {
+                    // only a command of its own in the command name position
+                    let w = Word::literal(&format!("(({}))", expression));
                     self.consume_token();
-                    let target = match self.consume_token() {
-                        Some(Token::Word(w)) => w.clone(),
-                        _ => panic!("Expected target after redirection"),
-                    };
-                    let (fd, rtype) = match op {
-                        Operator::Output(None) => (1, RedirectionType::Output),
-                        Operator::Output(Some(x)) => (x, RedirectionType::Output),
-                        Operator::Append(Some(x)) => (x, RedirectionType::Append),
-                        Operator::Append(None) => (1, RedirectionType::Append),
-                        Operator::Input => (0, RedirectionType::Input),
+                    match name {
+                        Some(_) => args.push(w),
+                        None => name = Some(w),
+                    }
+                }
+                Token::Word(_) | Token::Reserved(_) => {
+                    let w = match self.consume_token() {
+                        Some(Token::Reserved(reserved)) => Word::literal(&reserved.to_string()),
+                        Some(Token::Word(w)) => w,
                         _ => unreachable!(),
                     };
-                    redirs.push(Redirection {
-                        fd,
-                        direction: rtype,
-                        target,
-                    });
-                }
-                Token::Word(w) => {
-                    let w = w.clone();
-                    self.consume_token();
-                    if name.is_none() {
-                        name = Some(w);
-                    } else {
+                    if name.is_some() {
                         args.push(w);
+                    } else if let Some((var, value)) = w.as_assignment() {
+                        // assignments only count before the command name
+                        assignments.push(Assignment {
+                            name: var.to_string(),
+                            value,
+                        });
+                    } else {
+                        name = Some(w);
                     }
                 }
             }
         }
 
-        ASTNode::Command {
-            name: name.expect("Expected command name"),
+        // a command has to have something in it, so `| grep` and `;;` are errors
+        if self.position == first {
+            return Err(self.unexpected());
+        }
+        Ok(ASTNode::Command {
+            assignments,
+            name,
             args,
             redirections: redirs,
+            span: self.span_from(first),
+        })
+    }
+
+    /// Parses a redirection operator and its target, for the command that started at `first`.
+    fn parse_redirection(&mut self, first: usize) -> Result<Vec<Redirection>, ParseError> {
+        let Some(Token::Operator(op)) = self.consume_token() else {
+            unreachable!()
+        };
+        let target = match self.peek_token() {
+            Some(Token::Word(w)) => w.clone(),
+            Some(_) => return Err(self.unexpected()),
+            // the line ending is what turned up instead of a target
+            None => {
+                let end = self.span_from(first).end;
+                return Err(ParseError::UnexpectedToken {
+                    token: "newline".to_string(),
+                    span: Span { start: end, end },
+                });
+            }
+        };
+        self.consume_token();
+        Ok(Self::make_redirections(op, target))
+    }
+
+    /// Parses a compound command starting with `reserved`, along with any redirections after it.
+    fn parse_compound(&mut self, reserved: Reserved) -> Result<ASTNode, ParseError> {
+        let first = self.position;
+        let command = match reserved {
+            Reserved::If => self.parse_if()?,
+            Reserved::While | Reserved::Until => {
+                self.consume_token();
+                let condition = self.parse_body(&[Reserved::Do])?;
+                self.expect(Reserved::Do)?;
+                let body = self.parse_body(&[Reserved::Done])?;
+                self.expect(Reserved::Done)?;
+                CompoundCommand::While {
+                    until: reserved == Reserved::Until,
+                    condition: Box::new(condition),
+                    body: Box::new(body),
+                }
+            }
+            Reserved::For => self.parse_for()?,
+            Reserved::Case => self.parse_case()?,
+            // words like `then` and `done` only go after the start of their compound command
+            _ => return Err(self.unexpected()),
+        };
+
+        let mut redirections = Vec::new();
+        loop {
+            match self.peek_token() {
+                Some(Token::Operator(op)) if !op.is_control() => {
+                    redirections.extend(self.parse_redirection(first)?)
+                }
+                // a compound command can't have arguments, so `fi foo` is an error
+                Some(Token::Word(_) | Token::Arithmetic(_) | Token::Reserved(_)) => {
+                    return Err(self.unexpected())
+                }
+                _ => break,
+            }
+        }
+        Ok(ASTNode::Compound {
+            command,
+            redirections,
+            span: self.span_from(first),
+        })
+    }
+
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    fn parse_if(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.consume_token();
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let condition = self.parse_body(&[Reserved::Then])?;
+            self.expect(Reserved::Then)?;
+            let body = self.parse_body(&[Reserved::Elif, Reserved::Else, Reserved::Fi])?;
+            branches.push((condition, body));
+
+            match self.peek_token() {
+                Some(Token::Reserved(Reserved::Elif)) => {
+                    self.consume_token();
+                }
+                Some(Token::Reserved(Reserved::Else)) => {
+                    self.consume_token();
+                    otherwise = Some(Box::new(self.parse_body(&[Reserved::Fi])?));
+                    self.expect(Reserved::Fi)?;
+                    break;
+                }
+                _ => {
+                    self.expect(Reserved::Fi)?;
+                    break;
+                }
+            }
+        }
+        Ok(CompoundCommand::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    /// `for name [in words...]; do list; done` or `for ((init; condition; update)); do list; done`
+    fn parse_for(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.consume_token();
+        if let Some(Token::Arithmetic(expression)) = self.peek_token() {
+            let mut parts = split_expression(expression).into_iter();
+            let (Some(init), Some(condition), Some(update), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(self.unexpected());
+            };
+            self.consume_token();
+            return Ok(CompoundCommand::ArithmeticFor {
+                init,
+                condition,
+                update,
+                body: Box::new(self.parse_do_group()?),
+            });
+        }
+
+        let name = match self.peek_token() {
+            Some(Token::Word(word)) if word.as_literal().is_some_and(is_valid_name) => {
+                word.to_string()
+            }
+            _ => return Err(self.unexpected()),
+        };
+        self.consume_token();
+        self.skip_newlines();
+        let mut words = None;
+        if self.next_is_in() {
+            self.consume_token();
+            let mut list = Vec::new();
+            while let Some(Token::Word(word)) = self.peek_token() {
+                list.push(word.clone());
+                self.consume_token();
+            }
+            words = Some(list);
+        }
+        Ok(CompoundCommand::For {
+            name,
+            words,
+            body: Box::new(self.parse_do_group()?),
+        })
+    }
+
+    /// The `; do list; done` that ends a `for` loop.
+    fn parse_do_group(&mut self) -> Result<ASTNode, ParseError> {
+        if let Some(Token::Operator(Operator::Semi)) = self.peek_token() {
+            self.consume_token();
+        }
+        self.skip_newlines();
+        self.expect(Reserved::Do)?;
+        let body = self.parse_body(&[Reserved::Done])?;
+        self.expect(Reserved::Done)?;
+        Ok(body)
+    }
+
+    /// `case word in [(]pattern[|pattern]...) list;; ... esac`
+    fn parse_case(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.consume_token();
+        let word = match self.peek_token() {
+            Some(Token::Word(word)) => word.clone(),
+            _ => return Err(self.unexpected()),
+        };
+        self.consume_token();
+        self.skip_newlines();
+        if !self.next_is_in() {
+            return Err(self.unexpected());
+        }
+        self.consume_token();
+
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek_token() {
+                Some(Token::Reserved(Reserved::Esac)) => {
+                    self.consume_token();
+                    break;
+                }
+                Some(Token::Operator(Operator::LeftParen)) => {
+                    self.consume_token();
+                }
+                _ => {}
+            }
+
+            let mut patterns = Vec::new();
+            loop {
+                // a pattern can be any word, reserved or not
+                match self.peek_token() {
+                    Some(Token::Word(word)) => patterns.push(word.clone()),
+                    Some(Token::Reserved(reserved)) => {
+                        patterns.push(Word::literal(&reserved.to_string()))
+                    }
+                    _ => return Err(self.unexpected()),
+                }
+                self.consume_token();
+                match self.peek_token() {
+                    Some(Token::Operator(Operator::Pipe)) => self.consume_token(),
+                    Some(Token::Operator(Operator::RightParen)) => break,
+                    _ => return Err(self.unexpected()),
+                };
+            }
+            self.consume_token();
+
+            let body = Self::sequence(self.parse_list(&[Reserved::Esac])?);
+            items.push(CaseItem { patterns, body });
+            match self.peek_token() {
+                Some(Token::Operator(Operator::DoubleSemi)) => {
+                    self.consume_token();
+                }
+                // the last item doesn't need its ;;
+                Some(Token::Reserved(Reserved::Esac)) => {}
+                _ => return Err(self.unexpected()),
+            }
+        }
+        Ok(CompoundCommand::Case { word, items })
+    }
+
+    fn make_redirections(op: Operator, target: Word) -> Vec<Redirection> {
+        let file = |fd, direction| Redirection {
+            fd,
+            direction,
+            target: RedirectionTarget::File(target.clone()),
+        };
+        let dup = |fd, direction| {
+            let target = match target.as_literal() {
+                Some("-") => RedirectionTarget::Close,
+                t => match t
+                    .filter(|t| t.bytes().all(|b| b.is_ascii_digit()))
+                    .and_then(|t| t.parse::<RawFd>().ok())
+                {
+                    Some(n) => RedirectionTarget::Fd(n),
+                    None => RedirectionTarget::File(target.clone()),
+                },
+            };
+            Redirection {
+                fd,
+                direction,
+                target,
+            }
+        };
+        // &> is stdout to the target followed by 2>&1
+        let stderr_to_stdout = Redirection {
+            fd: 2,
+            direction: RedirectionType::Output,
+            target: RedirectionTarget::Fd(1),
+        };
+
+        match op {
+            Operator::Output(fd) => vec![file(fd.unwrap_or(1), RedirectionType::Output)],
+            Operator::Append(fd) => vec![file(fd.unwrap_or(1), RedirectionType::Append)],
+            Operator::Input(fd) => vec![file(fd.unwrap_or(0), RedirectionType::Input)],
+            Operator::ReadWrite(fd) => vec![file(fd.unwrap_or(0), RedirectionType::ReadWrite)],
+            Operator::DupInput(fd) => vec![dup(fd.unwrap_or(0), RedirectionType::Input)],
+            Operator::DupOutput(fd) => match dup(fd.unwrap_or(1), RedirectionType::Output) {
+                // >&word with a non-numeric word is the same as &>word
+                r @ Redirection {
+                    target: RedirectionTarget::File(_),
+                    ..
+                } if fd.is_none() => vec![r, stderr_to_stdout],
+                r => vec![r],
+            },
+            Operator::HereDoc(fd) | Operator::HereDocStripTabs(fd) => vec![Redirection {
+                fd: fd.unwrap_or(0),
+                direction: RedirectionType::Input,
+                target: RedirectionTarget::HereDoc(target),
+            }],
+            Operator::HereString(fd) => {
+                let mut text = target;
+                text.parts.push(WordPart::SingleQuoted("\n".to_string()));
+                vec![Redirection {
+                    fd: fd.unwrap_or(0),
+                    direction: RedirectionType::Input,
+                    target: RedirectionTarget::HereDoc(text),
+                }]
+            }
+            Operator::OutputAll => vec![file(1, RedirectionType::Output), stderr_to_stdout],
+            Operator::AppendAll => vec![file(1, RedirectionType::Append), stderr_to_stdout],
+            Operator::Pipe
+            | Operator::And
+            | Operator::Or
+            | Operator::Semi
+            | Operator::DoubleSemi
+            | Operator::LeftParen
+            | Operator::RightParen
+            | Operator::Newline
+            | Operator::Background => unreachable!(),
+        }
+    }
+}
+
+/// Splits the expression of `for ((init; condition; update))` at its unquoted `;`s, leaving out
+/// the parts that are blank.
+fn split_expression(expression: &Word) -> Vec<Option<Word>> {
+    let mut words = vec![Word { parts: Vec::new() }];
+    for part in &expression.parts {
+        let WordPart::Literal(text) = part else {
+            words.last_mut().unwrap().parts.push(part.clone());
+            continue;
+        };
+        for (i, piece) in text.split(';').enumerate() {
+            if i > 0 {
+                words.push(Word { parts: Vec::new() });
+            }
+            if !piece.is_empty() {
+                words
+                    .last_mut()
+                    .unwrap()
+                    .parts
+                    .push(WordPart::Literal(piece.to_string()));
+            }
+        }
+    }
+    words
+        .into_iter()
+        .map(|word| Some(word).filter(|word| !word.to_string().trim().is_empty()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn parse(source: &str) -> ASTNode {
+        Parser::new(Lexer::new(source).lex()).parse().unwrap()
+    }
+
+    fn parse_error(source: &str) -> ParseError {
+        Parser::new(Lexer::new(source).lex()).parse().unwrap_err()
+    }
+
+    fn compound(source: &str) -> CompoundCommand {
+        match parse(source) {
+            ASTNode::Compound { command, .. } => command,
+            node => panic!("expected a compound command: {:?}", node),
+        }
+    }
+
+    fn texts(words: &[Word]) -> Vec<String> {
+        words.iter().map(Word::to_string).collect()
+    }
+
+    /// A redirection written out the long way, like `2>&1` or `1>out`.
+    fn describe(redirection: &Redirection) -> String {
+        let operator = match redirection.direction {
+            RedirectionType::Input => "<",
+            RedirectionType::Output => ">",
+            RedirectionType::Append => ">>",
+            RedirectionType::ReadWrite => "<>",
+        };
+        let target = match &redirection.target {
+            RedirectionTarget::File(path) => path.to_string(),
+            RedirectionTarget::Fd(fd) => format!("&{}", fd),
+            RedirectionTarget::Close => "&-".to_string(),
+            RedirectionTarget::HereDoc(body) => format!("<{:?}", body.unquoted_text()),
+        };
+        format!("{}{}{}", redirection.fd, operator, target)
+    }
+
+    fn redirections(source: &str) -> Vec<String> {
+        match parse(source) {
+            ASTNode::Command { redirections, .. } => redirections.iter().map(describe).collect(),
+            node => panic!("expected a command: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn splits_pipelines_into_stages() {
+        match parse("cat f | grep a | wc -l") {
+            ASTNode::Pipeline(stages) => {
+                let names: Vec<String> = stages
+                    .iter()
+                    .map(|stage| match stage {
+                        ASTNode::Command { name, .. } => name.as_ref().unwrap().to_string(),
+                        stage => panic!("unexpected stage: {:?}", stage),
+                    })
+                    .collect();
+                assert_eq!(names, ["cat", "grep", "wc"]);
+            }
+            node => panic!("expected a pipeline: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn quoted_pipes_are_arguments() {
+        match parse("echo 'a | b' c\\|d") {
+            ASTNode::Command { name, args, .. } => {
+                assert_eq!(name, Some(Word::literal("echo")));
+                let args: Vec<String> = args.iter().map(Word::unquoted_text).collect();
+                assert_eq!(args, ["a | b", "c|d"]);
+            }
+            node => panic!("expected a command: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn collects_redirections_in_order() {
+        match parse("wc -l < in 2>> log > out") {
+            ASTNode::Command {
+                args, redirections, ..
+            } => {
+                assert_eq!(args, [Word::literal("-l")]);
+                let redirections: Vec<String> = redirections.iter().map(describe).collect();
+                assert_eq!(redirections, ["0<in", "2>>log", "1>out"]);
+            }
+            node => panic!("expected a command: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn parses_duplication_and_closing() {
+        assert_eq!(redirections("a 2>&1 >&2"), ["2>&1", "1>&2"]);
+        assert_eq!(redirections("a <&- 2>&-"), ["0<&-", "2>&-"]);
+        assert_eq!(redirections("a <&0"), ["0<&0"]);
+    }
+
+    #[test]
+    fn output_all_is_output_then_stderr_to_stdout() {
+        assert_eq!(redirections("a &> f"), ["1>f", "2>&1"]);
+        assert_eq!(redirections("a &>> f"), ["1>>f", "2>&1"]);
+        assert_eq!(redirections("a >& f"), ["1>f", "2>&1"]);
+    }
+
+    #[test]
+    fn parses_numbered_descriptors() {
+        assert_eq!(
+            redirections("a 3> f 4>&3 5<> g 3>&-"),
+            ["3>f", "4>&3", "5<>g", "3>&-"]
+        );
+    }
+
+    #[test]
+    fn large_descriptor_numbers_stay_redirections() {
+        assert_eq!(redirections("a 300> f >&4096"), ["300>f", "1>&4096"]);
+        // a word that isn't all digits is a file, as with >&word
+        assert_eq!(redirections("a 2>&+1"), ["2>+1"]);
+    }
+
+    #[test]
+    fn reads_here_document_bodies_from_the_following_lines() {
+        assert_eq!(
+            redirections("cat <<EOF\nhello\n  world\nEOF\n"),
+            [r#"0<<"hello\n  world\n""#]
+        );
+        // several on one line take their bodies in order
+        assert_eq!(
+            redirections("cat <<A 3<<B\na\nA\nb\nB\n"),
+            [r#"0<<"a\n""#, r#"3<<"b\n""#]
+        );
+    }
+
+    #[test]
+    fn strips_leading_tabs_with_dash() {
+        assert_eq!(
+            redirections("cat <<-END\n\tone\n\t\ttwo\n  three\n\tEND\n"),
+            [r#"0<<"one\ntwo\n  three\n""#]
+        );
+        // without the dash, a tabbed delimiter doesn't end the body
+        assert_eq!(redirections("cat <<END\n\tEND\nEND\n"), [r#"0<<"\tEND\n""#]);
+    }
+
+    #[test]
+    fn here_strings_get_a_newline() {
+        assert_eq!(redirections("cat <<< word"), [r#"0<<"word\n""#]);
+    }
+
+    #[test]
+    fn nodes_span_from_their_first_command_to_their_last() {
+        let source = "a x; b | c && d";
+        let ASTNode::Sequence(nodes) = parse(source) else {
+            panic!("expected a sequence");
+        };
+        let spans: Vec<&str> = nodes
+            .iter()
+            .map(|node| &source[node.span().start..node.span().end])
+            .collect();
+        assert_eq!(spans, ["a x", "b | c && d"]);
+    }
+
+    #[test]
+    fn reports_end_of_input_after_an_operator() {
+        let error = parse_error("a |");
+        assert!(matches!(error, ParseError::UnexpectedEof { .. }));
+        assert_eq!(error.position(), 3);
+        assert_eq!(parse_error("a &&\n\n").position(), 6);
+    }
+
+    #[test]
+    fn reports_the_unexpected_token_and_where_it_is() {
+        match parse_error("a && | b") {
+            ParseError::UnexpectedToken { token, span } => {
+                assert_eq!(token, "|");
+                assert_eq!(span.start, 5);
+            }
+            error => panic!("unexpected error: {:?}", error),
+        }
+        assert_eq!(parse_error("; a").position(), 0);
+        assert_eq!(
+            parse_error("cat <").to_string(),
+            "syntax error near unexpected token `newline'"
+        );
+    }
+
+    #[test]
+    fn parses_case_items() {
+        let CompoundCommand::Case { word, items } =
+            compound("case $x in\n  a|b) echo ab;;\n  (*.rs) ;;\n  in | esac) echo\nesac")
+        else {
+            panic!("expected a case command");
+        };
+        assert_eq!(word.to_string(), "$x");
+        let patterns: Vec<Vec<String>> = items.iter().map(|item| texts(&item.patterns)).collect();
+        assert_eq!(patterns, [vec!["a", "b"], vec!["*.rs"], vec!["in", "esac"]]);
+        assert!(matches!(&items[1].body, ASTNode::Sequence(nodes) if nodes.is_empty()));
+        assert!(
+            matches!(compound("case x in esac"), CompoundCommand::Case { items, .. } if items.is_empty())
+        );
+    }
+
+    #[test]
+    fn rejects_case_without_in_or_a_closing_paren() {
+        assert_eq!(
+            parse_error("case x a) ;; esac").to_string(),
+            "syntax error near unexpected token `a'"
+        );
+        assert!(
+            matches!(parse_error("case x in a b) esac"), ParseError::UnexpectedToken { token, .. } if token == "b")
+        );
+        assert!(matches!(
+            parse_error("case x in a) echo"),
+            ParseError::UnexpectedEof { .. }
+        ));
+    }
+
+    #[test]
+    fn parses_for_loops() {
+        let CompoundCommand::For { name, words, .. } =
+            compound("for f in a 'b c'; do echo $f; done")
+        else {
+            panic!("expected a for loop");
+        };
+        assert_eq!(name, "f");
+        assert_eq!(texts(&words.unwrap()), ["a", "'b c'"]);
+        // without `in`, and with `do` on the next line
+        let CompoundCommand::For { words, .. } = compound("for f\ndo echo; done") else {
+            panic!("expected a for loop");
+        };
+        assert!(words.is_none());
+        let CompoundCommand::For { words, .. } = compound("for f in; do echo; done") else {
+            panic!("expected a for loop");
+        };
+        assert_eq!(words.unwrap(), []);
+    }
+
+    #[test]
+    fn parses_arithmetic_for_loops() {
+        let CompoundCommand::ArithmeticFor {
+            init,
+            condition,
+            update,
+            ..
+        } = compound("for ((i = 0; i < 3; i++)); do echo; done")
+        else {
+            panic!("expected an arithmetic for loop");
+        };
+        assert_eq!(init.unwrap().to_string(), "i = 0");
+        assert_eq!(condition.unwrap().to_string(), " i < 3");
+        assert_eq!(update.unwrap().to_string(), " i++");
+        let CompoundCommand::ArithmeticFor {
+            init,
+            condition,
+            update,
+            ..
+        } = compound("for ((;;)); do echo; done")
+        else {
+            panic!("expected an arithmetic for loop");
+        };
+        assert!(init.is_none() && condition.is_none() && update.is_none());
+        assert!(matches!(
+            parse_error("for ((i; i)); do echo; done"),
+            ParseError::UnexpectedToken { .. }
+        ));
+        assert!(matches!(
+            parse_error("for 1x in a; do echo; done"),
+            ParseError::UnexpectedToken { .. }
+        ));
+    }
+
+    #[test]
+    fn parses_if_with_elif_and_else() {
+        let CompoundCommand::If {
+            branches,
+            otherwise,
+        } = compound("if a; then b; elif c\nthen d; else e; fi")
+        else {
+            panic!("expected an if command");
+        };
+        assert_eq!(branches.len(), 2);
+        assert!(otherwise.is_some());
+        assert_eq!(
+            parse_error("if a; then fi").to_string(),
+            "syntax error near unexpected token `fi'"
+        );
+        assert!(matches!(
+            parse_error("if a; then b"),
+            ParseError::UnexpectedEof { .. }
+        ));
+    }
+
+    #[test]
+    fn takes_redirections_after_the_closing_word() {
+        match parse("while a; do b; done < in > out") {
+            ASTNode::Compound { redirections, .. } => {
+                assert_eq!(
+                    redirections.iter().map(describe).collect::<Vec<_>>(),
+                    ["0<in", "1>out"]
+                );
+            }
+            node => panic!("expected a compound command: {:?}", node),
         }
     }
 }
@@ -0,0 +1,387 @@
+/// How patterns are matched, following the `shopt` options that affect it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchOptions {
+    /// `?(...)`, `*(...)`, `+(...)`, `@(...)` and `!(...)` groups.
+    pub extglob: bool,
+    pub ignore_case: bool,
+}
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    AnyChar,                     // ?
+    AnyString,                   // *
+    Set(Vec<char>),              // [...], as the characters between the brackets
+    Group(char, Vec<Vec<Node>>), // an extglob group: its kind and its alternatives
+}
+
+/// A shell pattern, as used by `${VAR#pattern}` and pathname expansion. `*` matches any run of
+/// characters, `?` any single character and `[...]` one character out of a set, while a
+/// backslash makes the next character match only itself.
+#[derive(Debug)]
+pub struct Pattern {
+    nodes: Vec<Node>,
+    ignore_case: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str, options: MatchOptions) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut position = 0;
+        let nodes = parse(&chars, &mut position, options.extglob, false);
+        Self {
+            nodes,
+            ignore_case: options.ignore_case,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        self.match_nodes(&self.nodes, &text)
+    }
+
+    fn match_nodes(&self, nodes: &[Node], text: &[char]) -> bool {
+        let Some((node, rest)) = nodes.split_first() else {
+            return text.is_empty();
+        };
+        match node {
+            Node::Char(c) => {
+                text.first().is_some_and(|&t| self.same_char(*c, t))
+                    && self.match_nodes(rest, &text[1..])
+            }
+            Node::AnyChar => !text.is_empty() && self.match_nodes(rest, &text[1..]),
+            // a run of stars matches the same as one
+            Node::AnyString if matches!(rest.first(), Some(Node::AnyString)) => {
+                self.match_nodes(rest, text)
+            }
+            Node::AnyString => (0..=text.len()).any(|i| self.match_nodes(rest, &text[i..])),
+            Node::Set(set) => {
+                text.first().is_some_and(|&t| self.in_set(set, t))
+                    && self.match_nodes(rest, &text[1..])
+            }
+            Node::Group(kind, alternatives) => (0..=text.len()).any(|i| {
+                self.match_group(*kind, alternatives, &text[..i])
+                    && self.match_nodes(rest, &text[i..])
+            }),
+        }
+    }
+
+    /// Whether all of `text` matches an extglob group.
+    fn match_group(&self, kind: char, alternatives: &[Vec<Node>], text: &[char]) -> bool {
+        let any = |text: &[char]| alternatives.iter().any(|alt| self.match_nodes(alt, text));
+        match kind {
+            '@' => any(text),
+            '?' => text.is_empty() || any(text),
+            '*' => self.match_repeated(alternatives, text, 0),
+            '+' => self.match_repeated(alternatives, text, 1),
+            '!' => !any(text),
+            _ => false,
+        }
+    }
+
+    /// Whether `text` is at least `min` matches of the alternatives in a row.
+    fn match_repeated(&self, alternatives: &[Vec<Node>], text: &[char], min: usize) -> bool {
+        if text.is_empty() {
+            return min == 0;
+        }
+        (1..=text.len()).any(|i| {
+            alternatives
+                .iter()
+                .any(|alt| self.match_nodes(alt, &text[..i]))
+                && self.match_repeated(alternatives, &text[i..], min.saturating_sub(1))
+        })
+    }
+
+    fn same_char(&self, a: char, b: char) -> bool {
+        match self.ignore_case {
+            true => a.to_lowercase().eq(b.to_lowercase()),
+            false => a == b,
+        }
+    }
+
+    fn in_set(&self, set: &[char], c: char) -> bool {
+        let negated = matches!(set.first(), Some('!' | '^'));
+        let in_range = |start: char, end: char| match self.ignore_case {
+            true => [c.to_ascii_lowercase(), c.to_ascii_uppercase()]
+                .iter()
+                .any(|c| (start..=end).contains(c)),
+            false => (start..=end).contains(&c),
+        };
+        let mut i = negated as usize;
+        let mut matched = false;
+        while i < set.len() {
+            if let Some(len) = class_len(&set[i..]) {
+                let name: String = set[i + 2..i + len - 2].iter().collect();
+                matched |= self.in_class(&name, c);
+                i += len;
+                continue;
+            }
+            let start = match set[i] {
+                '\\' if i + 1 < set.len() => {
+                    i += 1;
+                    set[i]
+                }
+                ch => ch,
+            };
+            if set.get(i + 1) == Some(&'-') && i + 2 < set.len() {
+                matched |= in_range(start, set[i + 2]);
+                i += 3;
+            } else {
+                matched |= self.same_char(start, c);
+                i += 1;
+            }
+        }
+        matched != negated
+    }
+
+    /// Whether `c` is in a character class like `[:digit:]`. Unknown classes match nothing.
+    fn in_class(&self, name: &str, c: char) -> bool {
+        match name {
+            "alnum" => c.is_alphanumeric(),
+            "alpha" => c.is_alphabetic(),
+            "blank" => c == ' ' || c == '\t',
+            "cntrl" => c.is_control(),
+            "digit" => c.is_ascii_digit(),
+            "graph" => !c.is_control() && !c.is_whitespace(),
+            // ignoring case, either case of a letter is in both
+            "lower" | "upper" if self.ignore_case => c.is_alphabetic(),
+            "lower" => c.is_lowercase(),
+            "upper" => c.is_uppercase(),
+            "print" => !c.is_control(),
+            "punct" => c.is_ascii_punctuation(),
+            "space" => c.is_whitespace(),
+            "xdigit" => c.is_ascii_hexdigit(),
+            _ => false,
+        }
+    }
+}
+
+/// Parses pattern characters into nodes, up to the end or, inside an extglob group, up to the
+/// `|` or `)` ending the current alternative.
+fn parse(chars: &[char], position: &mut usize, extglob: bool, in_group: bool) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    while let Some(&c) = chars.get(*position) {
+        if in_group && matches!(c, '|' | ')') {
+            break;
+        }
+        *position += 1;
+        let is_group = extglob
+            && matches!(c, '?' | '*' | '+' | '@' | '!')
+            && chars.get(*position) == Some(&'(');
+        let node = match c {
+            _ if is_group => {
+                *position += 1;
+                let mut alternatives = Vec::new();
+                loop {
+                    alternatives.push(parse(chars, position, extglob, true));
+                    match chars.get(*position) {
+                        Some('|') => *position += 1,
+                        Some(')') => {
+                            *position += 1;
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+                Node::Group(c, alternatives)
+            }
+            '*' => Node::AnyString,
+            '?' => Node::AnyChar,
+            '[' => match bracket_len(&chars[*position - 1..]) {
+                Some(len) => {
+                    let set = chars[*position..*position + len - 2].to_vec();
+                    *position += len - 1;
+                    Node::Set(set)
+                }
+                // an unterminated [ is just a character
+                None => Node::Char('['),
+            },
+            '\\' if *position < chars.len() => {
+                *position += 1;
+                Node::Char(chars[*position - 1])
+            }
+            c => Node::Char(c),
+        };
+        nodes.push(node);
+    }
+    nodes
+}
+
+/// The length of the bracket expression at the start of `pattern`, brackets included, or
+/// `None` if it's missing its `]`.
+fn bracket_len(pattern: &[char]) -> Option<usize> {
+    let mut i = 1;
+    if matches!(pattern.get(i), Some('!' | '^')) {
+        i += 1;
+    }
+    // a ] right after the [ is part of the set
+    if pattern.get(i) == Some(&']') {
+        i += 1;
+    }
+    loop {
+        if let Some(len) = pattern.get(i..).and_then(class_len) {
+            i += len;
+            continue;
+        }
+        match *pattern.get(i)? {
+            ']' => return Some(i + 1),
+            '\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+}
+
+/// The length of the character class like `[:alpha:]` at the start of `chars`, if there's one.
+fn class_len(chars: &[char]) -> Option<usize> {
+    if !chars.starts_with(&['[', ':']) {
+        return None;
+    }
+    let name_len = chars[2..]
+        .iter()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .count();
+    match chars[2 + name_len..].starts_with(&[':', ']']) {
+        true => Some(name_len + 4),
+        false => None,
+    }
+}
+
+/// Escapes `text` so it only matches itself, as quoted text in a pattern does.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '*' | '?' | '[' | ']' | '\\' | '(' | ')' | '|' | '+' | '@' | '!'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Whether `pattern` has any unescaped `*`, `?` or `[`, or an extglob group, which make it
+/// more than a literal.
+pub fn has_wildcards(pattern: &str, extglob: bool) -> bool {
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            '+' | '@' | '!' if extglob && chars.peek() == Some(&'(') => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// The text a pattern without wildcards matches, i.e. with its escapes removed.
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern, MatchOptions::default()).matches(text)
+    }
+
+    fn extglob_matches(pattern: &str, text: &str) -> bool {
+        let options = MatchOptions {
+            extglob: true,
+            ..MatchOptions::default()
+        };
+        Pattern::new(pattern, options).matches(text)
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*", ""));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*b*b", "abcbb"));
+        assert!(!matches("*.rs", "main.rs.bak"));
+    }
+
+    #[test]
+    fn matches_bracket_expressions() {
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[a-c]x", "dx"));
+        assert!(matches("[!a-c]x", "dx"));
+        assert!(matches("[^a]", "b"));
+        assert!(matches("[]]", "]"));
+        // an unterminated bracket is an ordinary character
+        assert!(matches("[a", "[a"));
+    }
+
+    #[test]
+    fn backslash_makes_characters_literal() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("a\\?", "a?"));
+    }
+
+    #[test]
+    fn finds_unescaped_wildcards() {
+        assert!(has_wildcards("a*", false));
+        assert!(has_wildcards("[ab]", false));
+        assert!(!has_wildcards("a\\*b", false));
+        assert!(!has_wildcards("plain", false));
+        assert!(!has_wildcards("@(a)", false));
+        assert!(has_wildcards("@(a)", true));
+    }
+
+    #[test]
+    fn escaped_text_matches_only_itself() {
+        let text = "a*b?[c]\\";
+        assert!(matches(&escape(text), text));
+        assert!(!matches(&escape("a*"), "abc"));
+        assert_eq!(unescape(&escape(text)), text);
+    }
+
+    #[test]
+    fn matches_extglob_groups() {
+        assert!(extglob_matches("@(a|b).rs", "b.rs"));
+        assert!(!extglob_matches("@(a|b).rs", "ab.rs"));
+        assert!(extglob_matches("?(x)y", "y"));
+        assert!(extglob_matches("?(x)y", "xy"));
+        assert!(!extglob_matches("?(x)y", "xxy"));
+        assert!(extglob_matches("*(ab)c", "ababc"));
+        assert!(!extglob_matches("+(ab)c", "c"));
+        assert!(extglob_matches("+(a|b)", "abba"));
+        assert!(extglob_matches("!(*.o)", "main.c"));
+        assert!(!extglob_matches("!(*.o)", "main.o"));
+        // without extglob the parentheses are plain characters
+        assert!(!matches("@(a)", "a"));
+        assert!(matches("@(a)", "@(a)"));
+    }
+
+    #[test]
+    fn ignores_case_when_asked() {
+        let options = MatchOptions {
+            ignore_case: true,
+            ..MatchOptions::default()
+        };
+        assert!(Pattern::new("*.RS", options).matches("main.rs"));
+        assert!(Pattern::new("[a-c]", options).matches("B"));
+        assert!(!matches("*.RS", "main.rs"));
+    }
+}
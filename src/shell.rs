@@ -0,0 +1,616 @@
+use crate::commands::*;
+use crate::completer::MyHelper;
+use crate::enums::{Command, LoopControl};
+use crate::expand;
+use crate::expand::ExpansionError;
+use crate::jobs;
+use crate::jobs::{Job, JobTable, ProcessGroup};
+use crate::lexer::Lexer;
+use crate::options::ShellOptions;
+use crate::parser::{ASTNode, Assignment, CaseItem, CompoundCommand, Parser, Redirection};
+use crate::util;
+use crate::util::Streams;
+use crate::variables::Variables;
+use crate::word::Word;
+use nix::fcntl::OFlag;
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::sys::wait::waitpid;
+use nix::unistd::{close, fork, getpid, pipe2, ForkResult, Pid};
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::{io, process};
+
+pub struct Shell {
+    pub editor: Editor<MyHelper, DefaultHistory>,
+    pub last_saved_history_idx: usize,
+    /// Descriptors every command starts with; `exec` redirections change these for good.
+    pub fds: Streams,
+    /// Exit status of the most recent command, i.e. `$?`.
+    pub last_status: i32,
+    pub jobs: JobTable,
+    /// Pid of the most recent background job, i.e. `$!`.
+    pub last_background_pid: Option<Pid>,
+    pub vars: Variables,
+    /// The shell's name, i.e. `$0`.
+    pub name: String,
+    /// `$1`, `$2` and so on.
+    pub positional: Vec<String>,
+    /// Pid of the shell itself, which subshells keep reporting as `$$`.
+    pub pid: Pid,
+    /// The source of the command line being run, which the spans in its nodes point into.
+    pub source: String,
+    /// Options set with `shopt`.
+    pub options: ShellOptions,
+    /// Whether this is a forked child, like a pipeline stage or `$(...)`, which leaves the
+    /// history file to the interactive shell.
+    pub is_subshell: bool,
+    /// Whether the command being run is all that's left for this forked child to do, so an
+    /// external one can take over the process instead of being forked again.
+    exec_in_place: bool,
+    /// How many loops the command being run is inside of.
+    loop_depth: usize,
+    /// A `break` or `continue` that still has to leave the commands around it.
+    loop_control: Option<LoopControl>,
+    /// Exit status of the last command substitution in the command being expanded, which is the
+    /// status of a command that only assigns variables.
+    substitution_status: Option<i32>,
+}
+
+impl Shell {
+    pub fn new(
+        editor: Editor<MyHelper, DefaultHistory>,
+        last_saved_history_idx: usize,
+        name: String,
+        positional: Vec<String>,
+    ) -> Self {
+        Self {
+            editor,
+            last_saved_history_idx,
+            fds: Streams::inherit().expect("Failed to duplicate standard streams"),
+            last_status: 0,
+            jobs: JobTable::new(),
+            last_background_pid: None,
+            vars: Variables::from_env(),
+            name,
+            positional,
+            pid: getpid(),
+            source: String::new(),
+            options: ShellOptions::default(),
+            is_subshell: false,
+            exec_in_place: false,
+            loop_depth: 0,
+            loop_control: None,
+            substitution_status: None,
+        }
+    }
+
+    /// Announces background jobs that finished since the last prompt.
+    pub fn report_jobs(&mut self) {
+        for line in self.jobs.reap() {
+            eprintln!("{}", line);
+        }
+    }
+
+    /// Runs a node, recording its exit status as `$?` and returning it.
+    pub fn run_node(&mut self, node: ASTNode) -> i32 {
+        let status = match node {
+            ASTNode::Command {
+                assignments,
+                name,
+                args,
+                redirections,
+                ..
+            } => self.run_command(&assignments, name, &args, redirections),
+            ASTNode::Pipeline(pipeline) => self.run_pipeline(pipeline),
+            // after a `break` or `continue`, nothing else in the loop body runs
+            ASTNode::And(left, right) => match self.run_node(*left) {
+                0 if self.loop_control.is_none() => self.run_node(*right),
+                status => status,
+            },
+            ASTNode::Or(left, right) => match self.run_node(*left) {
+                status if status == 0 || self.loop_control.is_some() => status,
+                _ => self.run_node(*right),
+            },
+            ASTNode::Sequence(nodes) => {
+                let mut status = 0;
+                for node in nodes {
+                    status = self.run_node(node);
+                    if self.loop_control.is_some() {
+                        break;
+                    }
+                }
+                status
+            }
+            ASTNode::Background(node) => self.run_background(*node),
+            ASTNode::Arithmetic { expression, .. } => {
+                match expand::evaluate_arithmetic(self, &expression) {
+                    Ok(0) => 1,
+                    Ok(_) => 0,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        1
+                    }
+                }
+            }
+            ASTNode::Compound {
+                command,
+                redirections,
+                ..
+            } => self.run_compound(command, &redirections),
+        };
+        self.last_status = status;
+        status
+    }
+
+    /// Runs a compound command in the shell itself, with its redirections in place of the
+    /// shell's descriptors until it's done.
+    fn run_compound(&mut self, command: CompoundCommand, redirections: &[Redirection]) -> i32 {
+        if redirections.is_empty() {
+            return self.run_compound_command(command);
+        }
+        let streams = expand::expand_redirections(self, redirections)
+            .map_err(|e| e.to_string())
+            .and_then(|redirections| {
+                util::check_streams(&self.fds, redirections).map_err(|e| e.to_string())
+            });
+        let streams = match streams {
+            Ok(streams) => streams,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        let saved = std::mem::replace(&mut self.fds, streams);
+        let status = self.run_compound_command(command);
+        self.fds = saved;
+        status
+    }
+
+    fn run_compound_command(&mut self, command: CompoundCommand) -> i32 {
+        match command {
+            CompoundCommand::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    let status = self.run_node(condition);
+                    if self.loop_control.is_some() {
+                        return status;
+                    }
+                    if status == 0 {
+                        return self.run_node(body);
+                    }
+                }
+                match otherwise {
+                    Some(otherwise) => self.run_node(*otherwise),
+                    None => 0,
+                }
+            }
+            CompoundCommand::While {
+                until,
+                condition,
+                body,
+            } => {
+                self.loop_depth += 1;
+                let mut status = 0;
+                loop {
+                    let succeeded = self.run_node((*condition).clone()) == 0;
+                    if self.loop_finished() || succeeded == until {
+                        break;
+                    }
+                    status = self.run_node((*body).clone());
+                    if self.loop_finished() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                status
+            }
+            CompoundCommand::For { name, words, body } => {
+                let values = match words {
+                    Some(words) => match expand::expand_words(self, &words) {
+                        Ok(values) => values,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            return 1;
+                        }
+                    },
+                    None => self.positional.clone(),
+                };
+                self.loop_depth += 1;
+                let mut status = 0;
+                for value in values {
+                    if let Err(e) = self.vars.set(&name, &value) {
+                        eprintln!("{}: readonly variable", e.name);
+                        status = 1;
+                        break;
+                    }
+                    status = self.run_node((*body).clone());
+                    if self.loop_finished() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                status
+            }
+            CompoundCommand::ArithmeticFor {
+                init,
+                condition,
+                update,
+                body,
+            } => {
+                let evaluate = |shell: &mut Self, expression: &Option<Word>| match expression {
+                    Some(expression) => expand::evaluate_arithmetic(shell, expression),
+                    None => Ok(1),
+                };
+                if let Err(e) = evaluate(self, &init) {
+                    eprintln!("{}", e);
+                    return 1;
+                }
+                self.loop_depth += 1;
+                let mut status = 0;
+                loop {
+                    match evaluate(self, &condition) {
+                        Ok(0) => break,
+                        Ok(_) => {}
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            status = 1;
+                            break;
+                        }
+                    }
+                    status = self.run_node((*body).clone());
+                    if self.loop_finished() {
+                        break;
+                    }
+                    if let Err(e) = evaluate(self, &update) {
+                        eprintln!("{}", e);
+                        status = 1;
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                status
+            }
+            CompoundCommand::Case { word, items } => self.run_case(&word, items),
+        }
+    }
+
+    /// Whether a loop should stop after running part of itself, which it does for a `break`, or
+    /// a `continue` meant for a loop further out. Either way, the loops left to go through count
+    /// down by one.
+    fn loop_finished(&mut self) -> bool {
+        match self.loop_control.take() {
+            None | Some(LoopControl::Continue(1)) => false,
+            Some(LoopControl::Break(1)) => true,
+            Some(LoopControl::Break(count)) => {
+                self.loop_control = Some(LoopControl::Break(count - 1));
+                true
+            }
+            Some(LoopControl::Continue(count)) => {
+                self.loop_control = Some(LoopControl::Continue(count - 1));
+                true
+            }
+        }
+    }
+
+    /// Runs the first `case` item with a pattern matching the word, trying patterns in order.
+    fn run_case(&mut self, word: &Word, items: Vec<CaseItem>) -> i32 {
+        let text = match expand::expand_case_word(self, word) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        for item in items {
+            for pattern in &item.patterns {
+                match expand::expand_pattern(self, pattern) {
+                    Ok(pattern) if pattern.matches(&text) => return self.run_node(item.body),
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return 1;
+                    }
+                }
+            }
+        }
+        0
+    }
+
+    /// Runs `body` in a forked child that exits with its status. The child joins `group` if
+    /// there is one, and otherwise stays in the shell's process group.
+    fn fork_subshell(
+        &mut self,
+        group: Option<ProcessGroup>,
+        body: impl FnOnce(&mut Self) -> i32,
+    ) -> nix::Result<Pid> {
+        // SAFETY: the shell is single-threaded, so the child can safely keep running Rust code
+        match unsafe { fork() }? {
+            ForkResult::Child => {
+                let _ = match group {
+                    Some(group) => group.join(),
+                    None => jobs::reset_signals(),
+                };
+                // Rust ignores SIGPIPE, but a subshell writing into a closed pipe should just die
+                // SAFETY: resetting to the default handler doesn't run any code
+                let _ = unsafe { signal(Signal::SIGPIPE, SigHandler::SigDfl) };
+                self.jobs = JobTable::subshell();
+                self.is_subshell = true;
+                self.exec_in_place = false;
+                let status = body(self);
+                let _ = io::stdout().flush();
+                process::exit(status);
+            }
+            ForkResult::Parent { child } => {
+                if let Some(group) = group {
+                    group.admit(child);
+                }
+                Ok(child)
+            }
+        }
+    }
+
+    /// Runs `source` in a subshell and returns what it wrote to stdout, minus trailing newlines,
+    /// for `$(...)`. Its exit status becomes `$?`.
+    pub fn substitute_command(&mut self, source: &str) -> String {
+        let node = match Parser::new(Lexer::new(source).lex()).parse() {
+            Ok(node) => node,
+            Err(e) => {
+                eprintln!("{}", e);
+                self.last_status = 2;
+                self.substitution_status = Some(2);
+                return String::new();
+            }
+        };
+        let (read_end, write_end) = match pipe2(OFlag::O_CLOEXEC) {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                eprintln!("pipe: {}", e);
+                return String::new();
+            }
+        };
+
+        let output_fd = read_end.as_raw_fd();
+        // the substitution stays in the shell's process group, so it's interrupted along with
+        // the command it's part of
+        let child = self.fork_subshell(None, |shell| {
+            shell.source = source.to_string();
+            let _ = close(output_fd);
+            shell.fds.set(1, write_end);
+            shell.run_node(node)
+        });
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("fork: {}", e);
+                return String::new();
+            }
+        };
+
+        let mut output = Vec::new();
+        let _ = File::from(read_end).read_to_end(&mut output);
+        let status = util::wait_status(waitpid(child, None));
+        self.last_status = status;
+        self.substitution_status = Some(status);
+
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+        output
+    }
+
+    /// A node as it was written, falling back to how it displays if its span doesn't fit the
+    /// source.
+    fn source_text(&self, node: &ASTNode) -> String {
+        let span = node.span();
+        match self.source.get(span.start..span.end) {
+            Some(text) if !text.is_empty() => text.to_string(),
+            _ => node.to_string(),
+        }
+    }
+
+    fn run_background(&mut self, node: ASTNode) -> i32 {
+        let command = self.source_text(&node);
+        let group = self.jobs.process_group(None, false);
+        match self.fork_subshell(group, |shell| shell.run_node(node)) {
+            Ok(child) => {
+                let id = self.jobs.add(Job::new(child, vec![child], command));
+                eprintln!("[{}] {}", id, child);
+                self.last_background_pid = Some(child);
+                0
+            }
+            Err(e) => {
+                eprintln!("fork: {}", e);
+                1
+            }
+        }
+    }
+
+    fn run_pipeline(&mut self, pipeline: Vec<ASTNode>) -> i32 {
+        let stages: Vec<String> = pipeline.iter().map(|node| self.source_text(node)).collect();
+        let command = stages.join(" | ");
+
+        // every stage runs in its own forked child so builtins can sit anywhere in the pipeline,
+        // and a stage that's just an external command execs in that child
+        let last = pipeline.len() - 1;
+        let mut children = Vec::new();
+        let mut prev_read: Option<OwnedFd> = None;
+
+        for (i, node) in pipeline.into_iter().enumerate() {
+            let (read_end, write_end) = if i < last {
+                match pipe2(OFlag::O_CLOEXEC) {
+                    Ok((read_end, write_end)) => (Some(read_end), Some(write_end)),
+                    Err(e) => {
+                        eprintln!("pipe: {}", e);
+                        break;
+                    }
+                }
+            } else {
+                (None, None)
+            };
+
+            // the first stage leads the pipeline's process group
+            let pgid = children.first().copied();
+            let next_stage_input = read_end.as_ref().map(|fd| fd.as_raw_fd());
+            let stage = |shell: &mut Self| {
+                if let Some(fd) = next_stage_input {
+                    let _ = close(fd);
+                }
+                if let Some(fd) = prev_read {
+                    shell.fds.set(0, fd);
+                }
+                if let Some(fd) = write_end {
+                    shell.fds.set(1, fd);
+                }
+                shell.exec_in_place = matches!(node, ASTNode::Command { .. });
+                shell.run_node(node)
+            };
+            let group = self.jobs.process_group(pgid, true);
+            match self.fork_subshell(group, stage) {
+                Ok(child) => children.push(child),
+                Err(e) => eprintln!("fork: {}", e),
+            }
+
+            // the parent keeps only the read end for the next stage; the write end was dropped
+            // along with the closure
+            prev_read = read_end;
+        }
+
+        match children.first() {
+            // the pipeline's status is the status of its last stage
+            Some(&pgid) => self.jobs.wait_foreground(Job::new(pgid, children, command)),
+            None => 1,
+        }
+    }
+
+    fn run_command(
+        &mut self,
+        assignments: &[Assignment],
+        name: Option<Word>,
+        args: &[Word],
+        redirections: Vec<Redirection>,
+    ) -> i32 {
+        self.substitution_status = None;
+        let expand = |shell: &mut Self| -> Result<_, ExpansionError> {
+            let words = expand::expand_command(shell, name.iter().chain(args))?;
+            // without a command, assignments set shell variables, each one in time for the next
+            // to see it; with one, they only go into its environment
+            let mut env = Vec::new();
+            for assignment in assignments {
+                let value = expand::expand_assignment(shell, &assignment.value)?;
+                match words.is_empty() {
+                    true => shell
+                        .vars
+                        .set(&assignment.name, &value)
+                        .map_err(|e| ExpansionError::new(e.name, "readonly variable"))?,
+                    false => env.push((assignment.name.clone(), value)),
+                }
+            }
+            let redirections = expand::expand_redirections(shell, &redirections)?;
+            Ok((words, env, redirections))
+        };
+        let (mut words, env, redirections) = match expand(self) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+
+        let streams = match util::check_streams(&self.fds, redirections) {
+            Ok(streams) => streams,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        // a command line that expanded to nothing only performs its assignments and redirections
+        if words.is_empty() {
+            return self.substitution_status.unwrap_or(0);
+        }
+        let name = words.remove(0);
+        self.run_command_stream(name, &words, &env, streams)
+    }
+
+    fn run_command_stream(
+        &mut self,
+        name: String,
+        args: &[String],
+        env: &[(String, String)],
+        streams: Streams,
+    ) -> i32 {
+        if let Ok(command) = name.parse::<Command>() {
+            match command {
+                // external commands get the descriptors themselves rather than files
+                Command::External(path) if self.exec_in_place => {
+                    return exec_external(path, args, env, streams);
+                }
+                Command::External(path) => {
+                    let group = self.jobs.process_group(None, true);
+                    return match external_cmd(path, args, env, streams, group) {
+                        Ok(pid) => {
+                            let command = std::iter::once(&name).chain(args).cloned();
+                            let command = command.collect::<Vec<_>>().join(" ");
+                            self.jobs.wait_foreground(Job::new(pid, vec![pid], command))
+                        }
+                        Err(status) => status,
+                    };
+                }
+                Command::Exec => return exec_cmd(args, env, streams, &mut self.fds),
+                _ => {}
+            }
+
+            let (iostream, err_stream) = streams.into_files();
+            let iostream = &mut util::CheckedWriter::new(iostream);
+            let err_stream = &mut util::CheckedWriter::new(err_stream);
+            let editor = &mut self.editor;
+            let last_saved_history_idx = &mut self.last_saved_history_idx;
+            let status = match command {
+                Command::Exit => exit_cmd(
+                    args,
+                    err_stream,
+                    editor,
+                    last_saved_history_idx,
+                    self.last_status,
+                    self.is_subshell,
+                ),
+                Command::Echo => echo_cmd(args, iostream),
+                Command::Type => type_cmd(args, iostream, err_stream),
+                Command::External(_) | Command::Exec => unreachable!(),
+                Command::Pwd => pwd_cmd(iostream),
+                Command::Cd => cd_cmd(args, iostream, err_stream, &mut self.vars),
+                Command::History => history_cmd(args, iostream, editor, last_saved_history_idx),
+                Command::Jobs => jobs_cmd(args, iostream, &mut self.jobs),
+                Command::Fg => fg_cmd(args, iostream, err_stream, &mut self.jobs),
+                Command::Bg => bg_cmd(args, iostream, err_stream, &mut self.jobs),
+                Command::Disown => disown_cmd(args, err_stream, &mut self.jobs),
+                Command::Kill => kill_cmd(args, iostream, err_stream, &mut self.jobs),
+                Command::Export => export_cmd(args, iostream, err_stream, &mut self.vars),
+                Command::Unset => unset_cmd(args, err_stream, &mut self.vars),
+                Command::Readonly => readonly_cmd(args, iostream, err_stream, &mut self.vars),
+                Command::Shopt => shopt_cmd(args, iostream, err_stream, &mut self.options),
+                Command::Break => {
+                    break_cmd(args, err_stream, self.loop_depth, &mut self.loop_control)
+                }
+                Command::Continue => {
+                    continue_cmd(args, err_stream, self.loop_depth, &mut self.loop_control)
+                }
+                Command::Invalid => invalid_cmd(&name, err_stream),
+            };
+            // output that couldn't be written, as with `echo hi > /dev/full`, fails the builtin
+            match iostream.error.take() {
+                Some(e) => {
+                    let message = util::io_error_message(&e);
+                    let _ = writeln!(err_stream, "{}: write error: {}", name, message);
+                    1
+                }
+                None => status,
+            }
+        } else {
+            panic!("Error parsing command")
+        }
+    }
+}
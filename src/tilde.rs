@@ -0,0 +1,123 @@
+use crate::variables::Variables;
+use crate::word::{Word, WordPart};
+use nix::unistd::{getuid, User};
+
+/// Replaces a tilde prefix at the start of a word, like `~`, `~/src` or `~user/src`, with the
+/// directory it names. In an assignment, every `:` separated path in the value gets the same
+/// treatment, as in `PATH=~/bin:~alice/bin`. The directory counts as quoted, so it isn't split or
+/// globbed, and a prefix naming nothing is left alone.
+pub fn expand(word: &Word, vars: &Variables, assignment: bool) -> Word {
+    let mut parts = Vec::new();
+    for (i, part) in word.parts.iter().enumerate() {
+        let WordPart::Literal(text) = part else {
+            parts.push(part.clone());
+            continue;
+        };
+        // a prefix has to be unquoted, so it can't run into the part after this one
+        let followed = i + 1 < word.parts.len();
+        let segments: Vec<&str> = match assignment {
+            true => text.split(':').collect(),
+            false => vec![text],
+        };
+
+        let mut literal = String::new();
+        for (j, segment) in segments.iter().enumerate() {
+            if j > 0 {
+                literal.push(':');
+            }
+            let (prefix, rest) = segment.split_at(segment.find('/').unwrap_or(segment.len()));
+            let runs_on = rest.is_empty() && j == segments.len() - 1 && followed;
+            let dir = match (i == 0 || j > 0) && !runs_on {
+                true => prefix
+                    .strip_prefix('~')
+                    .and_then(|user| home_dir(user, vars)),
+                false => None,
+            };
+            match dir {
+                Some(dir) => {
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(WordPart::SingleQuoted(dir));
+                    literal.push_str(rest);
+                }
+                None => literal.push_str(segment),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(literal));
+        }
+    }
+    Word { parts }
+}
+
+/// Like [`expand`] for an argument of `export` or `readonly`, where a `NAME=value` argument gets
+/// the tildes of an assignment in its value, as in `export PATH=~/bin:$PATH`.
+pub fn expand_argument(word: &Word, vars: &Variables) -> Word {
+    let Some((name, value)) = word.as_assignment() else {
+        return expand(word, vars, false);
+    };
+    let mut parts = vec![WordPart::Literal(format!("{}=", name))];
+    parts.extend(expand(&value, vars, true).parts);
+    Word { parts }
+}
+
+/// The directory `~` followed by `user` stands for: a home directory, or for `~+` and `~-` the
+/// current and previous working directories.
+fn home_dir(user: &str, vars: &Variables) -> Option<String> {
+    let dir = match user {
+        "" => match vars.get("HOME") {
+            Some(home) => return Some(home.to_string()),
+            None => User::from_uid(getuid()).ok()??.dir,
+        },
+        "+" => match vars.get("PWD") {
+            Some(pwd) => return Some(pwd.to_string()),
+            None => std::env::current_dir().ok()?,
+        },
+        "-" => return vars.get("OLDPWD").map(str::to_string),
+        name => User::from_name(name).ok()??.dir,
+    };
+    dir.into_os_string().into_string().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Variables {
+        let mut vars = Variables::default();
+        vars.set("HOME", "/home/u").unwrap();
+        vars
+    }
+
+    #[test]
+    fn expands_after_equals_in_declaration_arguments() {
+        let word = Word::literal("P=~/bin:~/x");
+        let expanded = expand_argument(&word, &vars()).unquoted_text();
+        assert_eq!(expanded, "P=/home/u/bin:/home/u/x");
+    }
+
+    #[test]
+    fn leaves_other_arguments_as_plain_words() {
+        assert_eq!(
+            expand_argument(&Word::literal("~/a"), &vars()).unquoted_text(),
+            "/home/u/a"
+        );
+        assert_eq!(
+            expand_argument(&Word::literal("-p"), &vars()).unquoted_text(),
+            "-p"
+        );
+        // only assignments continue after a `:`
+        let word = Word::literal("a:~/b");
+        assert_eq!(expand_argument(&word, &vars()).unquoted_text(), "a:~/b");
+    }
+
+    #[test]
+    fn leaves_unknown_users_alone() {
+        let word = Word::literal("~no_such_user_here/x");
+        assert_eq!(
+            expand(&word, &vars(), false).unquoted_text(),
+            "~no_such_user_here/x"
+        );
+    }
+}
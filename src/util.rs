@@ -1,12 +1,18 @@
-use crate::parser::{Redirection, RedirectionType};
-use std::collections::HashMap;
+use crate::parser::{Redirection, RedirectionTarget, RedirectionType};
+use nix::errno::Errno;
+use nix::sys::wait::WaitStatus;
 use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::fs::File;
-use std::io::{BufRead, Read, Write};
+use std::io::{BufRead, Write};
+use std::os::fd::{AsFd, OwnedFd};
 use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
-use std::{fmt, io};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fmt, io, process};
 
 #[derive(Debug)]
 pub struct PathError;
@@ -18,6 +24,15 @@ impl fmt::Display for PathError {
 }
 
 pub fn check_path(cmd: &str) -> Result<PathBuf, PathError> {
+    if cmd.contains('/') {
+        // paths are run as given; whether they're executable is up to exec
+        let path = PathBuf::from(cmd);
+        return match path.is_file() {
+            true => Ok(path),
+            false => Err(PathError),
+        };
+    }
+
     let path_env = std::env::var("PATH").unwrap();
     let paths = path_env.split(":").collect::<Vec<&str>>();
 
@@ -58,28 +73,230 @@ pub fn get_path_executables() -> HashMap<String, PathBuf> {
 }
 
 pub fn get_path_exe_strings(exe: HashMap<String, PathBuf>) -> Vec<String> {
-    exe.iter().map(|(name, _)| name.to_string()).collect()
+    exe.keys().map(|name| name.to_string()).collect()
+}
+
+#[derive(Debug)]
+pub struct RedirectionError {
+    pub target: String,
+    pub source: io::Error,
+}
+
+impl fmt::Display for RedirectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.target, io_error_message(&self.source))
+    }
+}
+
+/// The shell's view of a child's exit status: its exit code, or 128 plus the signal that
+/// killed it.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+/// Like [`exit_code`], for children waited on with `waitpid(2)`.
+pub fn wait_status(status: nix::Result<WaitStatus>) -> i32 {
+    match status {
+        Ok(WaitStatus::Exited(_, code)) => code,
+        Ok(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+        _ => 1,
+    }
+}
+
+/// The line of `source` holding byte offset `position`, with a caret underneath pointing at it.
+pub fn point_at(source: &str, position: usize) -> String {
+    let position = position.min(source.len());
+    let line_start = source[..position].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[position..]
+        .find('\n')
+        .map_or(source.len(), |i| position + i);
+    let column = source[line_start..position].chars().count();
+    format!("{}\n{}^", &source[line_start..line_end], " ".repeat(column))
+}
+
+/// Describes an io error the way the C library would, without Rust's "(os error N)" suffix.
+pub fn io_error_message(e: &io::Error) -> String {
+    let message = e.to_string();
+    match e.raw_os_error() {
+        Some(code) => message
+            .trim_end_matches(&format!(" (os error {})", code))
+            .to_string(),
+        None => message,
+    }
+}
+
+/// A builtin's output stream that holds on to the first write that failed instead of failing,
+/// so the builtin runs to the end and the failure is reported once, like `echo: write error`.
+pub struct CheckedWriter<W> {
+    inner: W,
+    pub error: Option<io::Error>,
+}
+
+impl<W: Write> CheckedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, error: None }
+    }
+}
+
+impl<W: Write> Write for CheckedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.error.is_some() {
+            return Ok(buf.len());
+        }
+        match self.inner.write(buf) {
+            Ok(n) => Ok(n),
+            Err(e) => {
+                self.error = Some(e);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Err(e) = self.inner.flush() {
+            self.error.get_or_insert(e);
+        }
+        Ok(())
+    }
+}
+
+/// The open descriptors of a command by fd number. A missing entry is a closed fd.
+pub struct Streams {
+    fds: BTreeMap<u8, OwnedFd>,
+}
+
+impl Streams {
+    /// Duplicates of the shell's own standard streams.
+    pub fn inherit() -> io::Result<Self> {
+        let mut fds = BTreeMap::new();
+        fds.insert(0, io::stdin().as_fd().try_clone_to_owned()?);
+        fds.insert(1, io::stdout().as_fd().try_clone_to_owned()?);
+        fds.insert(2, io::stderr().as_fd().try_clone_to_owned()?);
+        Ok(Self { fds })
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let fds = self
+            .fds
+            .iter()
+            .map(|(&n, fd)| Ok((n, fd.try_clone()?)))
+            .collect::<io::Result<_>>()?;
+        Ok(Self { fds })
+    }
+
+    pub fn set(&mut self, fd: u8, owned: OwnedFd) {
+        self.fds.insert(fd, owned);
+    }
+
+    pub fn take(&mut self, fd: u8) -> Option<OwnedFd> {
+        self.fds.remove(&fd)
+    }
+
+    /// Descriptors other than stdin, stdout and stderr, e.g. from `3>file`.
+    pub fn take_extra(&mut self) -> Vec<(u8, OwnedFd)> {
+        let extra = self.fds.split_off(&3);
+        extra.into_iter().collect()
+    }
+
+    /// Duplicates the descriptor currently behind `fd`, like `dup(2)`.
+    fn dup(&self, fd: u8) -> io::Result<OwnedFd> {
+        match self.fds.get(&fd) {
+            Some(owned) => owned.try_clone(),
+            None => Err(bad_fd()),
+        }
+    }
+
+    /// A file writing to a duplicate of `fd`, or to nowhere if it's closed.
+    pub fn dup_file(&self, fd: u8) -> File {
+        match self.dup(fd) {
+            Ok(fd) => File::from(fd),
+            Err(_) => null_file(),
+        }
+    }
+
+    /// Stdout and stderr as files for builtins to write to, which is all they use. Closed
+    /// streams write nothing.
+    pub fn into_files(mut self) -> (File, File) {
+        let mut file = |n: u8| match self.take(n) {
+            Some(fd) => File::from(fd),
+            None => null_file(),
+        };
+        (file(1), file(2))
+    }
+}
+
+fn bad_fd() -> io::Error {
+    io::Error::from_raw_os_error(Errno::EBADF as i32)
+}
+
+fn null_file() -> File {
+    File::options()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .expect("Failed to open /dev/null")
 }
 
-fn create_file(target: &str) -> Result<Box<dyn Write>, io::Error> {
+fn open_file(target: &str) -> Result<OwnedFd, io::Error> {
+    let file = File::open(target)?;
+    Ok(file.into())
+}
+
+fn create_file(target: &str) -> Result<OwnedFd, io::Error> {
     let file = File::create(target)?;
-    Ok(Box::new(file))
+    Ok(file.into())
 }
 
-fn append_file(target: &str) -> Result<Box<dyn Write>, io::Error> {
+fn open_rw_file(target: &str) -> Result<OwnedFd, io::Error> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(target)?;
+    Ok(file.into())
+}
+
+/// Here-documents are written to an unlinked temporary file rather than a pipe, so bodies larger
+/// than the pipe buffer can't block the shell.
+fn here_doc_file(body: &str) -> Result<OwnedFd, io::Error> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("sh-thd-{}-{}", process::id(), n));
+    let mut file = fs::OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(&path)?;
+    let reader = File::open(&path);
+    let _ = fs::remove_file(&path);
+
+    file.write_all(body.as_bytes())?;
+    Ok(reader?.into())
+}
+
+fn append_file(target: &str) -> Result<OwnedFd, io::Error> {
     let file = fs::OpenOptions::new()
         .create(true)
         .append(true)
-        .open(&target)?;
-    Ok(Box::new(file))
+        .open(target)?;
+    Ok(file.into())
 }
 
+/// Resolves redirections into owned descriptors that can be used by builtins or handed to child
+/// processes. Streams that aren't redirected are duplicates of the ones in `base`.
 pub fn check_streams(
-    redirection: Vec<Redirection>,
-) -> (Box<dyn Read>, Box<dyn Write>, Box<dyn Write>) {
-    let input_stream: Box<dyn Read> = Box::new(io::stdin());
-    let mut iostream: Box<dyn Write> = Box::new(io::stdout());
-    let mut errstream: Box<dyn Write> = Box::new(io::stderr());
+    base: &Streams,
+    redirection: Vec<Redirection<String>>,
+) -> Result<Streams, RedirectionError> {
+    let mut streams = base.try_clone().map_err(|source| RedirectionError {
+        target: "redirection".to_string(),
+        source,
+    })?;
 
     for Redirection {
         fd,
@@ -87,26 +304,42 @@ pub fn check_streams(
         target,
     } in redirection
     {
-        match (fd, direction) {
-            (0, RedirectionType::Input) => {
-                todo!("Input")
-            }
-            (1, RedirectionType::Output) => {
-                iostream = create_file(&target).unwrap();
-            }
-            (2, RedirectionType::Output) => {
-                errstream = create_file(&target).unwrap();
-            }
-            (1, RedirectionType::Append) => {
-                iostream = append_file(&target).unwrap();
+        // sources get moved up to 256 and above before exec, so targets have to stay below that
+        let fd = u8::try_from(fd).map_err(|_| RedirectionError {
+            target: fd.to_string(),
+            source: bad_fd(),
+        })?;
+        // applied left to right, so `>file 2>&1` and `2>&1 >file` differ like in sh
+        let opened = match (&target, direction) {
+            (RedirectionTarget::File(path), RedirectionType::Input) => open_file(path).map(Some),
+            (RedirectionTarget::File(path), RedirectionType::Output) => create_file(path).map(Some),
+            (RedirectionTarget::File(path), RedirectionType::Append) => append_file(path).map(Some),
+            (RedirectionTarget::File(path), RedirectionType::ReadWrite) => {
+                open_rw_file(path).map(Some)
             }
-            (2, RedirectionType::Append) => {
-                errstream = append_file(&target).unwrap();
-            }
-            _ => unreachable!(),
+            (RedirectionTarget::HereDoc(body), _) => here_doc_file(body).map(Some),
+            (RedirectionTarget::Fd(source), _) => u8::try_from(*source)
+                .map_err(|_| bad_fd())
+                .and_then(|source| streams.dup(source))
+                .map(Some),
+            (RedirectionTarget::Close, _) => Ok(None),
+        };
+        let opened = opened.map_err(|source| RedirectionError {
+            target: match &target {
+                RedirectionTarget::File(path) => path.clone(),
+                RedirectionTarget::Fd(source) => source.to_string(),
+                RedirectionTarget::Close => "-".to_string(),
+                RedirectionTarget::HereDoc(_) => "here-document".to_string(),
+            },
+            source,
+        })?;
+
+        match opened {
+            Some(owned) => streams.set(fd, owned),
+            None => drop(streams.take(fd)),
         }
     }
-    (input_stream, iostream, errstream)
+    Ok(streams)
 }
 
 // https://doc.rust-lang.org/rust-by-example/std_misc/file/read_lines.html
@@ -128,13 +361,11 @@ pub fn read_history(history_file: &str) -> Vec<String> {
 }
 
 pub fn append_history(history: &[String], history_file: &str) {
-    if let Ok(mut file_ref) = fs::OpenOptions::new()
-        .append(true)
-        .write(true)
-        .open(history_file)
-    {
+    if let Ok(mut file_ref) = fs::OpenOptions::new().append(true).open(history_file) {
         for entry in history {
-            writeln!(file_ref, "{}", entry).unwrap();
+            if writeln!(file_ref, "{}", entry).is_err() {
+                break;
+            }
         }
     }
 }
@@ -142,10 +373,163 @@ pub fn write_history(history: &[String], history_file: &str) {
     if let Ok(mut file_ref) = fs::OpenOptions::new()
         .create(true)
         .write(true)
+        .truncate(true)
         .open(history_file)
     {
         for entry in history {
-            writeln!(file_ref, "{}", entry).unwrap();
+            if writeln!(file_ref, "{}", entry).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::fd::RawFd;
+    use std::os::unix::fs::MetadataExt;
+
+    /// A fresh directory under the system's temporary one, named after the test using it.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sh-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn redirect(fd: RawFd, direction: RedirectionType, target: &Path) -> Redirection<String> {
+        Redirection {
+            fd,
+            direction,
+            target: RedirectionTarget::File(target.to_str().unwrap().to_string()),
+        }
+    }
+
+    fn dup(fd: RawFd, source: RawFd) -> Redirection<String> {
+        Redirection {
+            fd,
+            direction: RedirectionType::Output,
+            target: RedirectionTarget::Fd(source),
         }
     }
+
+    fn close(fd: RawFd) -> Redirection<String> {
+        Redirection {
+            fd,
+            direction: RedirectionType::Output,
+            target: RedirectionTarget::Close,
+        }
+    }
+
+    fn redirected(redirections: Vec<Redirection<String>>) -> Result<Streams, RedirectionError> {
+        check_streams(&Streams::inherit().unwrap(), redirections)
+    }
+
+    /// The device and inode behind a descriptor, which tell whether two fds share a file.
+    fn identity(fd: Option<OwnedFd>) -> (u64, u64) {
+        let metadata = File::from(fd.unwrap()).metadata().unwrap();
+        (metadata.dev(), metadata.ino())
+    }
+
+    #[test]
+    fn reads_input_from_files() {
+        let dir = scratch_dir("input");
+        let input = dir.join("in");
+        fs::write(&input, "hello\n").unwrap();
+
+        let mut streams = redirected(vec![redirect(0, RedirectionType::Input, &input)]).unwrap();
+        let mut text = String::new();
+        File::from(streams.take(0).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "hello\n");
+    }
+
+    #[test]
+    fn later_redirections_of_a_stream_win() {
+        let dir = scratch_dir("later");
+        let (first, second) = (dir.join("a"), dir.join("b"));
+        let mut streams = redirected(vec![
+            redirect(1, RedirectionType::Output, &first),
+            redirect(1, RedirectionType::Output, &second),
+        ])
+        .unwrap();
+        File::from(streams.take(1).unwrap())
+            .write_all(b"x")
+            .unwrap();
+        // both are created, but only the last one is written to
+        assert_eq!(fs::read_to_string(&first).unwrap(), "");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "x");
+    }
+
+    #[test]
+    fn names_the_target_that_failed() {
+        let dir = scratch_dir("missing");
+        let missing = dir.join("missing");
+        let error = redirected(vec![redirect(0, RedirectionType::Input, &missing)])
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("{}: No such file or directory", missing.display())
+        );
+    }
+
+    #[test]
+    fn applies_duplications_left_to_right() {
+        let dir = scratch_dir("order");
+        let out = dir.join("out");
+        let file = File::create(&out).unwrap().metadata().unwrap();
+        let file = (file.dev(), file.ino());
+
+        // >out 2>&1 sends both to the file
+        let mut streams =
+            redirected(vec![redirect(1, RedirectionType::Output, &out), dup(2, 1)]).unwrap();
+        assert_eq!(identity(streams.take(1)), file);
+        assert_eq!(identity(streams.take(2)), file);
+
+        // 2>&1 >out leaves stderr on the old stdout
+        let mut shell = Streams::inherit().unwrap();
+        let mut streams =
+            redirected(vec![dup(2, 1), redirect(1, RedirectionType::Output, &out)]).unwrap();
+        assert_eq!(identity(streams.take(1)), file);
+        assert_eq!(identity(streams.take(2)), identity(shell.take(1)));
+    }
+
+    #[test]
+    fn closes_streams_and_rejects_duplicating_closed_ones() {
+        let mut streams = redirected(vec![close(1)]).unwrap();
+        assert!(streams.take(1).is_none());
+
+        let error = redirected(vec![close(1), dup(2, 1)]).err().unwrap();
+        assert_eq!(error.to_string(), "1: Bad file descriptor");
+    }
+
+    #[test]
+    fn opens_numbered_descriptors() {
+        let dir = scratch_dir("numbered");
+        let out = dir.join("out");
+        let mut streams =
+            redirected(vec![redirect(3, RedirectionType::Output, &out), dup(4, 3)]).unwrap();
+        let extra: Vec<u8> = streams.take_extra().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(extra, [3, 4]);
+        assert!(redirected(vec![dup(1, 5)]).is_err());
+    }
+
+    #[test]
+    fn rejects_descriptors_past_255() {
+        let dir = scratch_dir("large");
+        let out = dir.join("out");
+        let error = redirected(vec![redirect(300, RedirectionType::Output, &out)])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "300: Bad file descriptor");
+        assert!(!out.exists());
+
+        let error = redirected(vec![dup(1, 256)]).err().unwrap();
+        assert_eq!(error.to_string(), "256: Bad file descriptor");
+        assert!(redirected(vec![redirect(255, RedirectionType::Output, &out)]).is_ok());
+    }
 }
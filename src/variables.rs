@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::{env, fmt};
+
+#[derive(Debug, Clone, Default)]
+pub struct Variable {
+    /// `None` for a variable that was exported or made readonly before it got a value.
+    pub value: Option<String>,
+    pub exported: bool,
+    pub readonly: bool,
+}
+
+/// Assigning to or unsetting a readonly variable.
+#[derive(Debug)]
+pub struct ReadonlyError {
+    pub name: String,
+}
+
+impl fmt::Display for ReadonlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: readonly variable", self.name)
+    }
+}
+
+/// Shell variables, starting out as the environment the shell was started with. Exported
+/// variables are kept in the process environment too, so everything the shell starts inherits
+/// them.
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    vars: BTreeMap<String, Variable>,
+}
+
+impl Variables {
+    pub fn from_env() -> Self {
+        let vars = env::vars()
+            .map(|(name, value)| {
+                let var = Variable {
+                    value: Some(value),
+                    exported: true,
+                    readonly: false,
+                };
+                (name, var)
+            })
+            .collect();
+        Self { vars }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name)?.value.as_deref()
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), ReadonlyError> {
+        let var = self.vars.entry(name.to_string()).or_default();
+        if var.readonly {
+            return Err(ReadonlyError {
+                name: name.to_string(),
+            });
+        }
+        var.value = Some(value.to_string());
+        if var.exported {
+            env::set_var(name, value);
+        }
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<(), ReadonlyError> {
+        if self.vars.get(name).is_some_and(|var| var.readonly) {
+            return Err(ReadonlyError {
+                name: name.to_string(),
+            });
+        }
+        if self.vars.remove(name).is_some_and(|var| var.exported) {
+            env::remove_var(name);
+        }
+        Ok(())
+    }
+
+    /// Exports a variable, or stops exporting it if `exported` is false.
+    pub fn export(&mut self, name: &str, exported: bool) {
+        let var = self.vars.entry(name.to_string()).or_default();
+        var.exported = exported;
+        match (&var.value, exported) {
+            (Some(value), true) => env::set_var(name, value),
+            _ => env::remove_var(name),
+        }
+    }
+
+    pub fn make_readonly(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default().readonly = true;
+    }
+
+    /// Variables in name order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Variable)> {
+        self.vars.iter()
+    }
+}
+
+/// Whether `name` can be a variable name: letters, digits and underscores, not starting with a
+/// digit.
+pub fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
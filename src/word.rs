@@ -0,0 +1,255 @@
+use crate::variables::is_valid_name;
+use std::fmt;
+
+/// A shell word as it was written. Keeping track of which parts were quoted lets expansion treat
+/// `$HOME` and `'$HOME'` differently long after lexing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    Literal(String),      // bare text
+    Escaped(char),        // \c
+    SingleQuoted(String), // '...'
+    // "...", which only contains literals, escapes and expansions
+    DoubleQuoted(Vec<WordPart>),
+    Param(Param),         // $NAME, ${NAME}, ${NAME:-word}, $?, $1
+    CommandSubst(String), // $(command) or `command`, as source text
+    Arithmetic(Word),     // $((expression))
+}
+
+/// A parameter expansion: the parameter and what to do with its value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub op: ParamOp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamOp {
+    Value,  // $NAME, ${NAME}
+    Length, // ${#NAME}
+    // ${NAME-word} and friends. With the colon, an empty value counts as unset.
+    Default {
+        colon: bool,
+        word: Word,
+    }, // -
+    Assign {
+        colon: bool,
+        word: Word,
+    }, // =
+    Error {
+        colon: bool,
+        word: Word,
+    }, // ?
+    Alternative {
+        colon: bool,
+        word: Word,
+    }, // +
+    RemovePrefix {
+        longest: bool,
+        pattern: Word,
+    }, // # and ##
+    RemoveSuffix {
+        longest: bool,
+        pattern: Word,
+    }, // % and %%
+    Replace {
+        anchor: ReplaceAnchor,
+        pattern: Word,
+        replacement: Word,
+    }, // /, //, /# and /%
+    Substring {
+        offset: Word,
+        length: Option<Word>,
+    }, // ${NAME:offset:length}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceAnchor {
+    First,
+    All,
+    Start,
+    End,
+}
+
+impl Param {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            op: ParamOp::Value,
+        }
+    }
+}
+
+impl Word {
+    pub fn literal(text: &str) -> Self {
+        Self {
+            parts: vec![WordPart::Literal(text.to_string())],
+        }
+    }
+
+    /// The text of a word made only of bare text, which is the only kind of word that can be an
+    /// operator or a reserved word.
+    pub fn as_literal(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(text)] => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn is_quoted(&self) -> bool {
+        self.parts.iter().any(|part| {
+            matches!(
+                part,
+                WordPart::Escaped(_) | WordPart::SingleQuoted(_) | WordPart::DoubleQuoted(_)
+            )
+        })
+    }
+
+    /// Splits `NAME=value` into the name and the value, if the word is an assignment. The name and
+    /// the `=` have to be bare text.
+    pub fn as_assignment(&self) -> Option<(&str, Word)> {
+        let Some(WordPart::Literal(first)) = self.parts.first() else {
+            return None;
+        };
+        let (name, value) = first.split_once('=')?;
+        if !is_valid_name(name) {
+            return None;
+        }
+        let mut parts = Vec::new();
+        if !value.is_empty() {
+            parts.push(WordPart::Literal(value.to_string()));
+        }
+        parts.extend(self.parts[1..].iter().cloned());
+        Some((name, Word { parts }))
+    }
+
+    /// The word with quotes removed but nothing expanded, as used for here-document delimiters.
+    pub fn unquoted_text(&self) -> String {
+        fn push_parts(text: &mut String, parts: &[WordPart]) {
+            for part in parts {
+                match part {
+                    WordPart::Literal(s) | WordPart::SingleQuoted(s) => text.push_str(s),
+                    WordPart::Escaped(c) => text.push(*c),
+                    WordPart::DoubleQuoted(parts) => push_parts(text, parts),
+                    WordPart::Param(param) => text.push_str(&param.to_string()),
+                    WordPart::CommandSubst(source) => {
+                        text.push_str("$(");
+                        text.push_str(source);
+                        text.push(')');
+                    }
+                    WordPart::Arithmetic(expression) => {
+                        text.push_str("$((");
+                        push_parts(text, &expression.parts);
+                        text.push_str("))");
+                    }
+                }
+            }
+        }
+        let mut text = String::new();
+        push_parts(&mut text, &self.parts);
+        text
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_parts(f, &self.parts)
+    }
+}
+
+/// Writes parts back out as shell source, bracing a parameter only where the text after it would
+/// otherwise run into its name.
+fn fmt_parts(f: &mut fmt::Formatter<'_>, parts: &[WordPart]) -> fmt::Result {
+    for (i, part) in parts.iter().enumerate() {
+        match part {
+            WordPart::Literal(text) => write!(f, "{}", text)?,
+            WordPart::Escaped(c) => write!(f, "\\{}", c)?,
+            WordPart::SingleQuoted(text) => write!(f, "'{}'", text)?,
+            WordPart::DoubleQuoted(parts) => {
+                write!(f, "\"")?;
+                fmt_parts(f, parts)?;
+                write!(f, "\"")?;
+            }
+            WordPart::Param(Param {
+                name,
+                op: ParamOp::Value,
+            }) => {
+                let next = match parts.get(i + 1) {
+                    Some(WordPart::Literal(text)) => text.chars().next(),
+                    _ => None,
+                };
+                let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                let is_digit = name.len() == 1 && name.starts_with(|c: char| c.is_ascii_digit());
+                let is_special = name.len() == 1 && !is_name && !is_digit;
+                let runs_on = next.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+                match ((is_name || is_digit) && !runs_on) || is_special {
+                    true => write!(f, "${}", name)?,
+                    false => write!(f, "${{{}}}", name)?,
+                }
+            }
+            WordPart::Param(param) => write!(f, "{}", param)?,
+            WordPart::CommandSubst(source) => write!(f, "$({})", source)?,
+            WordPart::Arithmetic(expression) => write!(f, "$(({}))", expression)?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let colon = |colon: &bool| match colon {
+            true => ":",
+            false => "",
+        };
+        let name = &self.name;
+        match &self.op {
+            ParamOp::Value => write!(f, "${{{}}}", name),
+            ParamOp::Length => write!(f, "${{#{}}}", name),
+            ParamOp::Default { colon: c, word } => write!(f, "${{{}{}-{}}}", name, colon(c), word),
+            ParamOp::Assign { colon: c, word } => write!(f, "${{{}{}={}}}", name, colon(c), word),
+            ParamOp::Error { colon: c, word } => write!(f, "${{{}{}?{}}}", name, colon(c), word),
+            ParamOp::Alternative { colon: c, word } => {
+                write!(f, "${{{}{}+{}}}", name, colon(c), word)
+            }
+            ParamOp::RemovePrefix { longest, pattern } => {
+                let op = match longest {
+                    true => "##",
+                    false => "#",
+                };
+                write!(f, "${{{}{}{}}}", name, op, pattern)
+            }
+            ParamOp::RemoveSuffix { longest, pattern } => {
+                let op = match longest {
+                    true => "%%",
+                    false => "%",
+                };
+                write!(f, "${{{}{}{}}}", name, op, pattern)
+            }
+            ParamOp::Replace {
+                anchor,
+                pattern,
+                replacement,
+            } => {
+                let op = match anchor {
+                    ReplaceAnchor::First => "/",
+                    ReplaceAnchor::All => "//",
+                    ReplaceAnchor::Start => "/#",
+                    ReplaceAnchor::End => "/%",
+                };
+                write!(f, "${{{}{}{}/{}}}", name, op, pattern, replacement)
+            }
+            ParamOp::Substring { offset, length } => {
+                write!(f, "${{{}:{}", name, offset)?;
+                if let Some(length) = length {
+                    write!(f, ":{}", length)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}